@@ -1,4 +1,7 @@
+use std::env;
+use std::process::Command;
 use std::ptr;
+use std::str;
 
 #[allow(dead_code)]
 struct Sample(usize);
@@ -67,10 +70,28 @@ fn test_ptr_layouts() {
     }
 }
 
+/// Returns the minor version of the compiler, if it can be determined.
+fn rustc_minor_version() -> Option<u32> {
+    let rustc = env::var_os("RUSTC")?;
+    let output = Command::new(rustc).arg("--version").output().ok()?;
+    let version = str::from_utf8(&output.stdout).ok()?;
+    let mut pieces = version.split('.');
+    if pieces.next() != Some("rustc 1") {
+        return None;
+    }
+    pieces.next()?.parse().ok()
+}
+
 fn main() {
     // NOTE: this will not protect from every possible case,
     // for example, rust may add one more fat pointer type which this test
     // will not check, host layout may be different from target layout,
     // and probably more.
     test_ptr_layouts();
+
+    // trait objects coerce to their supertraits since rust 1.86
+    println!("cargo:rustc-check-cfg=cfg(trait_upcasting)");
+    if rustc_minor_version().map_or(false, |minor| minor >= 86) {
+        println!("cargo:rustc-cfg=trait_upcasting");
+    }
 }
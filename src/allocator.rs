@@ -0,0 +1,98 @@
+use core::alloc::Layout;
+use core::ptr::NonNull;
+
+#[cfg(feature = "alloc")]
+use ::alloc::alloc;
+
+use crate::sptr;
+use crate::AllocError;
+
+/// An allocator that `SmallBox` uses to store values that do not fit in the inline space.
+///
+/// This is a minimal, stable counterpart of the unstable `core::alloc::Allocator` trait.
+/// It only needs to provide the two operations `SmallBox` relies on.
+///
+/// # Safety
+///
+/// A memory block returned by `allocate` must be valid for reads and writes of
+/// `layout.size()` bytes, aligned to `layout.align()`, and must stay valid until
+/// it is passed to `deallocate` on the same allocator (or a clone of it).
+pub unsafe trait Allocator {
+    /// Whether `allocate` can ever succeed for a layout of non-zero size.
+    ///
+    /// `SmallBox` refuses at compile time to box a value that may not fit in the space
+    /// with an allocator that can't allocate. It is `false` only for [`Global`] without
+    /// the `alloc` feature.
+    const CAN_ALLOCATE: bool = true;
+
+    /// Allocate a block of memory that fits `layout`.
+    ///
+    /// `layout` may be zero-sized, for values that are pinned.
+    fn allocate(&self, layout: Layout) -> Result<NonNull<u8>, AllocError>;
+
+    /// Deallocate a block of memory previously returned by `allocate`.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must have been returned by `allocate` on this allocator with the same `layout`,
+    /// and must not have been deallocated already.
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout);
+}
+
+unsafe impl<A: Allocator + ?Sized> Allocator for &A {
+    const CAN_ALLOCATE: bool = A::CAN_ALLOCATE;
+
+    #[inline]
+    fn allocate(&self, layout: Layout) -> Result<NonNull<u8>, AllocError> {
+        (**self).allocate(layout)
+    }
+
+    #[inline]
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        (**self).deallocate(ptr, layout)
+    }
+}
+
+/// The global memory allocator.
+///
+/// This is the default allocator of `SmallBox`, forwarding to the allocator
+/// registered with `#[global_allocator]`.
+///
+/// Without the `alloc` feature there is no global allocator to forward to,
+/// and every non-zero-sized allocation fails.
+#[derive(Copy, Clone, Default, Debug)]
+pub struct Global;
+
+unsafe impl Allocator for Global {
+    const CAN_ALLOCATE: bool = cfg!(feature = "alloc");
+
+    #[inline]
+    fn allocate(&self, layout: Layout) -> Result<NonNull<u8>, AllocError> {
+        if layout.size() == 0 {
+            // zero-sized allocations don't need any memory, hand out a well-aligned dangling pointer
+            return Ok(unsafe {
+                NonNull::new_unchecked(sptr::without_provenance_mut(layout.align()))
+            });
+        }
+
+        #[cfg(feature = "alloc")]
+        {
+            let ptr = unsafe { alloc::alloc(layout) };
+            NonNull::new(ptr).ok_or_else(|| AllocError::new(layout))
+        }
+
+        #[cfg(not(feature = "alloc"))]
+        Err(AllocError::new(layout))
+    }
+
+    #[inline]
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        #[cfg(feature = "alloc")]
+        if layout.size() != 0 {
+            alloc::dealloc(ptr.as_ptr(), layout)
+        }
+
+        #[cfg(not(feature = "alloc"))]
+        let _ = (ptr, layout);
+    }
+}
@@ -0,0 +1,100 @@
+use core::ptr;
+
+/// Values that can be cloned into the storage of a `SmallBox`, including unsized ones.
+///
+/// `SmallBox<T, Space>` implements `Clone` whenever `T: SmallClone`. This trait is
+/// implemented for every `T: Clone`, for slices `[T]` of `T: Clone` and for `str`,
+/// and can not be implemented by hand.
+///
+/// To clone trait objects, make `SmallClone` a supertrait of the trait: the clone
+/// then goes through the vtable. A clone is stored inline whenever the original is.
+///
+/// # Example
+///
+/// ```
+/// #[macro_use]
+/// extern crate smallbox;
+///
+/// # fn main() {
+/// use smallbox::space::S2;
+/// use smallbox::SmallBox;
+/// use smallbox::SmallClone;
+///
+/// trait Shape: SmallClone {
+///     fn area(&self) -> usize;
+/// }
+///
+/// #[derive(Clone)]
+/// struct Square(usize);
+///
+/// impl Shape for Square {
+///     fn area(&self) -> usize {
+///         self.0 * self.0
+///     }
+/// }
+///
+/// let shape: SmallBox<dyn Shape, S2> = smallbox!(Square(3));
+/// let cloned = shape.clone();
+///
+/// assert_eq!(cloned.area(), 9);
+/// assert!(!cloned.is_heap());
+/// # }
+/// ```
+pub trait SmallClone: sealed::Sealed {
+    #[doc(hidden)]
+    unsafe fn __clone_to(&self, dst: *mut u8, _: sealed::Private);
+}
+
+mod sealed {
+    pub trait Sealed {}
+
+    impl<T: Clone> Sealed for T {}
+    impl<T: Clone> Sealed for [T] {}
+    impl Sealed for str {}
+
+    pub struct Private;
+}
+
+impl<T: Clone> SmallClone for T {
+    unsafe fn __clone_to(&self, dst: *mut u8, _: sealed::Private) {
+        dst.cast::<T>().write(self.clone())
+    }
+}
+
+impl<T: Clone> SmallClone for [T] {
+    unsafe fn __clone_to(&self, dst: *mut u8, _: sealed::Private) {
+        let mut guard = PartialSlice {
+            ptr: dst.cast::<T>(),
+            len: 0,
+        };
+        for val in self {
+            guard.ptr.add(guard.len).write(val.clone());
+            guard.len += 1;
+        }
+        core::mem::forget(guard);
+    }
+}
+
+impl SmallClone for str {
+    unsafe fn __clone_to(&self, dst: *mut u8, _: sealed::Private) {
+        ptr::copy_nonoverlapping(self.as_ptr(), dst, self.len())
+    }
+}
+
+/// Clone `val` into `dst`, which must be valid for writes of the layout of `val`.
+#[inline]
+pub(crate) unsafe fn clone_to<T: ?Sized + SmallClone>(val: &T, dst: *mut u8) {
+    val.__clone_to(dst, sealed::Private)
+}
+
+/// Drops the elements written so far if cloning or iterating panics.
+pub(crate) struct PartialSlice<T> {
+    pub(crate) ptr: *mut T,
+    pub(crate) len: usize,
+}
+
+impl<T> Drop for PartialSlice<T> {
+    fn drop(&mut self) {
+        unsafe { ptr::drop_in_place(ptr::slice_from_raw_parts_mut(self.ptr, self.len)) }
+    }
+}
@@ -0,0 +1,434 @@
+use core::alloc::Layout;
+use core::cmp::Ordering;
+use core::fmt;
+use core::hash::Hash;
+use core::hash::{self};
+use core::marker::PhantomData;
+use core::mem::ManuallyDrop;
+use core::mem::MaybeUninit;
+use core::mem::{self};
+use core::ops;
+use core::ptr;
+use core::ptr::NonNull;
+
+use crate::error::handle_alloc_error;
+use crate::hook;
+#[cfg(not(feature = "alloc"))]
+use crate::stackbox::AssertFits;
+#[cfg(feature = "stats")]
+use crate::stats::EventKind;
+#[cfg(feature = "stats")]
+use crate::stats::{self};
+use crate::AllocError;
+use crate::Allocator;
+use crate::Global;
+use crate::SmallBox;
+
+/// A box for sized values that keeps the heap pointer in the inline space
+///
+/// `SmallBox` stores a pointer next to its space, which also carries the metadata of
+/// unsized values. For a sized `T`, whether the value fits in the space is known from
+/// the types alone, so `CompactBox` needs neither that pointer nor a discriminant:
+/// a value that fits in the size and the alignment of `Space` is stored inline, and
+/// any other value is stored on the heap, with the heap pointer written to the space.
+///
+/// The size of `CompactBox<T, Space>` is the size of `Space`, rounded up to hold a
+/// pointer, so `CompactBox<u64, S1>` is a single word where `SmallBox<u64, S1>` is two.
+///
+/// # Example
+///
+/// ```
+/// # #[cfg(feature = "alloc")]
+/// # {
+/// use core::mem;
+///
+/// use smallbox::space::S1;
+/// use smallbox::CompactBox;
+///
+/// let small: CompactBox<u64, S1> = CompactBox::new(42);
+/// let large: CompactBox<[u64; 4], S1> = CompactBox::new([1; 4]);
+///
+/// assert!(!small.is_heap());
+/// assert!(large.is_heap());
+/// assert_eq!(*small, 42);
+/// assert_eq!(large[3], 1);
+///
+/// assert_eq!(
+///     mem::size_of::<CompactBox<u64, S1>>(),
+///     mem::size_of::<usize>()
+/// );
+/// # }
+/// ```
+pub struct CompactBox<T, Space> {
+    storage: Storage<T, Space>,
+    _phantom: PhantomData<T>,
+}
+
+#[repr(C)]
+union Storage<T, Space> {
+    inline: ManuallyDrop<MaybeUninit<Space>>,
+    heap: NonNull<T>,
+}
+
+impl<T, Space> CompactBox<T, Space> {
+    /// Box value on stack or on heap depending on its size.
+    ///
+    /// Without the `alloc` feature, it fails to compile if the value doesn't fit
+    /// in the size or the alignment of `Space`.
+    #[inline]
+    #[track_caller]
+    pub fn new(val: T) -> CompactBox<T, Space> {
+        // without a global allocator there is nowhere to spill, so refuse at compile time
+        #[cfg(not(feature = "alloc"))]
+        #[allow(clippy::let_unit_value)]
+        let () = AssertFits::<T, Space>::ASSERT;
+        match Self::try_new_in_place(val) {
+            Ok(this) => this,
+            Err(err) => handle_alloc_error(err.layout()),
+        }
+    }
+
+    /// Box value on stack or on heap depending on its size, returning an error
+    /// if the heap allocation fails.
+    ///
+    /// The value is dropped if the allocation fails.
+    #[cfg(feature = "alloc")]
+    #[inline]
+    #[track_caller]
+    pub fn try_new(val: T) -> Result<CompactBox<T, Space>, AllocError> {
+        Self::try_new_in_place(val)
+    }
+
+    #[track_caller]
+    fn try_new_in_place(val: T) -> Result<CompactBox<T, Space>, AllocError> {
+        let layout = Layout::new::<T>();
+        let mut storage = Storage {
+            inline: ManuallyDrop::new(MaybeUninit::uninit()),
+        };
+
+        if Self::is_inline() {
+            #[cfg(feature = "stats")]
+            stats::record::<T, Space>(EventKind::Inline, layout);
+            let dst = if layout.size() == 0 {
+                NonNull::dangling().as_ptr()
+            } else {
+                ptr::addr_of_mut!(storage).cast::<T>()
+            };
+            unsafe { dst.write(val) };
+        } else {
+            hook::heap_fallback::<T, Space>(layout);
+            let heap_ptr = Global.allocate(layout)?.cast::<T>();
+            #[cfg(feature = "stats")]
+            stats::record::<T, Space>(EventKind::Heap, layout);
+            unsafe { heap_ptr.as_ptr().write(val) };
+            storage.heap = heap_ptr;
+        }
+
+        Ok(CompactBox {
+            storage,
+            _phantom: PhantomData,
+        })
+    }
+
+    /// Returns true if a `T` is stored on heap.
+    ///
+    /// This only depends on the types, a value that doesn't fit in the space is always
+    /// stored on heap, and zero-sized values are always stored inline.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # #[cfg(feature = "alloc")]
+    /// # {
+    /// use smallbox::space::S1;
+    /// use smallbox::CompactBox;
+    ///
+    /// let heaped: CompactBox<(usize, usize), S1> = CompactBox::new((0, 1));
+    /// assert!(heaped.is_heap());
+    /// # }
+    /// ```
+    #[inline]
+    pub fn is_heap(&self) -> bool {
+        !Self::is_inline()
+    }
+
+    #[inline]
+    fn is_inline() -> bool {
+        mem::size_of::<T>() == 0 || crate::stackbox::fits::<T, Space>()
+    }
+
+    /// Consumes the `CompactBox` and returns ownership of the boxed value
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # #[cfg(feature = "alloc")]
+    /// # {
+    /// use smallbox::space::S1;
+    /// use smallbox::CompactBox;
+    ///
+    /// let boxed: CompactBox<_, S1> = CompactBox::new([21usize, 56]);
+    /// assert_eq!(boxed.into_inner(), [21, 56]);
+    /// # }
+    /// ```
+    #[inline]
+    pub fn into_inner(self) -> T {
+        let mut this = ManuallyDrop::new(self);
+        unsafe {
+            let val = this.as_mut_ptr().read();
+            this.dealloc();
+            val
+        }
+    }
+
+    /// Convert the `CompactBox` into a `SmallBox` with the same space.
+    ///
+    /// A value on heap is handed over to the `SmallBox` as is, and an inline
+    /// value stays inline, so this never allocates.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # #[cfg(feature = "alloc")]
+    /// # {
+    /// use smallbox::space::S1;
+    /// use smallbox::CompactBox;
+    /// use smallbox::SmallBox;
+    ///
+    /// let compact: CompactBox<_, S1> = CompactBox::new([0usize; 4]);
+    /// let small: SmallBox<_, S1> = compact.into_smallbox();
+    /// assert!(small.is_heap());
+    /// assert_eq!(*small, [0; 4]);
+    /// # }
+    /// ```
+    #[inline]
+    pub fn into_smallbox(self) -> SmallBox<T, Space> {
+        let this = ManuallyDrop::new(self);
+        let val: &T = &this;
+        unsafe {
+            if this.is_heap() {
+                SmallBox::from_heap_unchecked(this.storage.heap, Global)
+            } else {
+                // already counted as inline by `CompactBox::new`
+                SmallBox::from_inline_unchecked(val, Global)
+            }
+        }
+    }
+
+    /// Free the heap memory, if any, without dropping the boxed value.
+    unsafe fn dealloc(&mut self) {
+        if self.is_heap() {
+            let layout = Layout::new::<T>();
+            #[cfg(feature = "stats")]
+            stats::record::<T, Space>(EventKind::Free, layout);
+            Global.deallocate(self.storage.heap.cast(), layout);
+        }
+    }
+
+    #[inline]
+    unsafe fn as_ptr(&self) -> *const T {
+        if mem::size_of::<T>() == 0 {
+            NonNull::dangling().as_ptr()
+        } else if self.is_heap() {
+            self.storage.heap.as_ptr()
+        } else {
+            ptr::addr_of!(self.storage).cast()
+        }
+    }
+
+    #[inline]
+    unsafe fn as_mut_ptr(&mut self) -> *mut T {
+        if mem::size_of::<T>() == 0 {
+            NonNull::dangling().as_ptr()
+        } else if self.is_heap() {
+            self.storage.heap.as_ptr()
+        } else {
+            ptr::addr_of_mut!(self.storage).cast()
+        }
+    }
+}
+
+impl<T, Space> From<CompactBox<T, Space>> for SmallBox<T, Space> {
+    fn from(compact: CompactBox<T, Space>) -> Self {
+        compact.into_smallbox()
+    }
+}
+
+impl<T, Space> ops::Deref for CompactBox<T, Space> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.as_ptr() }
+    }
+}
+
+impl<T, Space> ops::DerefMut for CompactBox<T, Space> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.as_mut_ptr() }
+    }
+}
+
+impl<T, Space> ops::Drop for CompactBox<T, Space> {
+    fn drop(&mut self) {
+        unsafe {
+            ptr::drop_in_place::<T>(&mut **self);
+            self.dealloc();
+        }
+    }
+}
+
+impl<T: Clone, Space> Clone for CompactBox<T, Space> {
+    #[track_caller]
+    fn clone(&self) -> Self {
+        CompactBox::new((**self).clone())
+    }
+}
+
+impl<T: fmt::Display, Space> fmt::Display for CompactBox<T, Space> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(&**self, f)
+    }
+}
+
+impl<T: fmt::Debug, Space> fmt::Debug for CompactBox<T, Space> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+impl<T: PartialEq, Space> PartialEq for CompactBox<T, Space> {
+    fn eq(&self, other: &CompactBox<T, Space>) -> bool {
+        PartialEq::eq(&**self, &**other)
+    }
+}
+
+impl<T: PartialOrd, Space> PartialOrd for CompactBox<T, Space> {
+    fn partial_cmp(&self, other: &CompactBox<T, Space>) -> Option<Ordering> {
+        PartialOrd::partial_cmp(&**self, &**other)
+    }
+}
+
+impl<T: Ord, Space> Ord for CompactBox<T, Space> {
+    fn cmp(&self, other: &CompactBox<T, Space>) -> Ordering {
+        Ord::cmp(&**self, &**other)
+    }
+}
+
+impl<T: Eq, Space> Eq for CompactBox<T, Space> {}
+
+impl<T: Hash, Space> Hash for CompactBox<T, Space> {
+    fn hash<H: hash::Hasher>(&self, state: &mut H) {
+        (**self).hash(state);
+    }
+}
+
+unsafe impl<T: Send, Space> Send for CompactBox<T, Space> {}
+unsafe impl<T: Sync, Space> Sync for CompactBox<T, Space> {}
+
+#[cfg(test)]
+mod tests {
+    use core::cell::Cell;
+    use core::mem;
+
+    use super::CompactBox;
+    use crate::space::*;
+    use crate::SmallBox;
+
+    #[test]
+    fn test_basic() {
+        let stacked: CompactBox<usize, S1> = CompactBox::new(1234);
+        assert!(!stacked.is_heap());
+        assert_eq!(*stacked, 1234);
+
+        let mut stacked: CompactBox<(u8, u16), S1> = CompactBox::new((1, 2));
+        stacked.1 = 3;
+        assert_eq!(*stacked, (1, 3));
+
+        let zst: CompactBox<(), [u8; 0]> = CompactBox::new(());
+        assert!(!zst.is_heap());
+        assert_eq!(zst.into_inner(), ());
+    }
+
+    #[test]
+    fn test_size() {
+        assert_eq!(mem::size_of::<CompactBox<u64, S1>>(), mem::size_of::<S1>());
+        assert_eq!(
+            mem::size_of::<CompactBox<[u64; 8], S4>>(),
+            mem::size_of::<S4>()
+        );
+        assert_eq!(
+            mem::size_of::<CompactBox<u8, [u8; 1]>>(),
+            mem::size_of::<usize>()
+        );
+        assert!(mem::size_of::<CompactBox<u64, S1>>() < mem::size_of::<SmallBox<u64, S1>>());
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn test_heap() {
+        let mut heaped: CompactBox<[usize; 4], S1> = CompactBox::new([1, 2, 3, 4]);
+        assert!(heaped.is_heap());
+        heaped[0] = 5;
+        assert_eq!(*heaped, [5, 2, 3, 4]);
+        assert_eq!(heaped.clone(), heaped);
+
+        let addr = (*heaped).as_ptr();
+        let small = heaped.into_smallbox();
+        assert!(small.is_heap());
+        assert_eq!((*small).as_ptr(), addr);
+        assert_eq!(small.into_inner(), [5, 2, 3, 4]);
+
+        let heaped: CompactBox<[usize; 4], S1> = CompactBox::try_new([1, 2, 3, 4]).unwrap();
+        assert_eq!(heaped.into_inner(), [1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn test_drop() {
+        #[allow(dead_code)]
+        struct Struct<'a>(&'a Cell<bool>, u8);
+        impl<'a> Drop for Struct<'a> {
+            fn drop(&mut self) {
+                self.0.set(true);
+            }
+        }
+
+        let flag = Cell::new(false);
+        let stacked: CompactBox<_, S2> = CompactBox::new(Struct(&flag, 0));
+        assert!(!flag.get());
+        drop(stacked);
+        assert!(flag.get());
+
+        let flag = Cell::new(false);
+        let stacked: CompactBox<_, S2> = CompactBox::new(Struct(&flag, 0));
+        let small: SmallBox<Struct, S2> = stacked.into();
+        assert!(!small.is_heap());
+        assert!(!flag.get());
+        drop(small);
+        assert!(flag.get());
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn test_drop_heap() {
+        #[allow(dead_code)]
+        struct Struct<'a>(&'a Cell<bool>, [usize; 4]);
+        impl<'a> Drop for Struct<'a> {
+            fn drop(&mut self) {
+                self.0.set(true);
+            }
+        }
+
+        let flag = Cell::new(false);
+        let heaped: CompactBox<_, S1> = CompactBox::new(Struct(&flag, [0; 4]));
+        assert!(heaped.is_heap());
+        drop(heaped);
+        assert!(flag.get());
+
+        let flag = Cell::new(false);
+        let heaped: CompactBox<_, S1> = CompactBox::new(Struct(&flag, [0; 4]));
+        let val = heaped.into_inner();
+        assert!(!flag.get());
+        drop(val);
+        assert!(flag.get());
+    }
+}
@@ -0,0 +1,269 @@
+use core::alloc::Layout;
+#[cfg(all(not(feature = "std"), feature = "core_error"))]
+use core::error::Error;
+use core::fmt;
+#[cfg(any(feature = "std", feature = "core_error"))]
+use core::ptr;
+#[cfg(feature = "std")]
+use std::error::Error;
+
+#[cfg(all(any(feature = "std", feature = "core_error"), feature = "alloc"))]
+use ::alloc::string::String;
+
+#[cfg(any(feature = "std", feature = "core_error"))]
+use crate::space::S2;
+#[cfg(any(feature = "std", feature = "core_error"))]
+use crate::Allocator;
+#[cfg(any(feature = "std", feature = "core_error"))]
+use crate::SmallBox;
+
+/// The error type returned by the fallible constructors of `SmallBox`,
+/// such as [`SmallBox::try_new`](crate::SmallBox::try_new).
+///
+/// Storing a value in the inline space never fails, so this error is only
+/// returned when the value has to fall back to the heap and the allocator
+/// cannot satisfy the request.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct AllocError {
+    layout: Layout,
+}
+
+impl AllocError {
+    pub(crate) fn new(layout: Layout) -> AllocError {
+        AllocError { layout }
+    }
+
+    /// Returns the layout of the heap allocation that failed.
+    #[inline]
+    pub fn layout(&self) -> Layout {
+        self.layout
+    }
+}
+
+impl fmt::Display for AllocError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "memory allocation of {} bytes failed",
+            self.layout.size()
+        )
+    }
+}
+
+#[cfg(any(feature = "std", feature = "core_error"))]
+impl Error for AllocError {}
+
+/// An error carrying only a message, stored inline when it is short enough
+///
+/// `SmallBox<dyn Error>` can be created from any error type with `From`, so `?` converts
+/// errors into it like into `Box<dyn Error>`. Unlike `Box<dyn Error>`, there is no
+/// conversion from `&str` or `String`, as it would overlap with the conversion from errors.
+/// Wrap messages in a `MessageError` instead.
+///
+/// A message of up to two `usize`s in bytes is stored inline, and only longer messages are
+/// stored on the heap. Without the `alloc` feature, longer messages can't be stored and cause
+/// a panic.
+///
+/// # Example
+///
+/// ```
+/// use std::error::Error;
+///
+/// use smallbox::space::S8;
+/// use smallbox::MessageError;
+/// use smallbox::SmallBox;
+///
+/// fn parse_digit(c: char) -> Result<u32, SmallBox<dyn Error + Send + Sync, S8>> {
+///     match c.to_digit(10) {
+///         Some(digit) => Ok(digit),
+///         None => Err(MessageError::from("not a digit").into()),
+///     }
+/// }
+///
+/// let err = parse_digit('x').unwrap_err();
+/// assert_eq!(err.to_string(), "not a digit");
+/// assert!(!err.is_heap());
+/// ```
+#[cfg(any(feature = "std", feature = "core_error"))]
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct MessageError(SmallBox<str, S2>);
+
+#[cfg(any(feature = "std", feature = "core_error"))]
+impl MessageError {
+    /// Returns the message.
+    #[inline]
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+#[cfg(any(feature = "std", feature = "core_error"))]
+impl From<&str> for MessageError {
+    #[track_caller]
+    fn from(message: &str) -> Self {
+        MessageError(SmallBox::from(message))
+    }
+}
+
+#[cfg(all(any(feature = "std", feature = "core_error"), feature = "alloc"))]
+impl From<String> for MessageError {
+    #[track_caller]
+    fn from(message: String) -> Self {
+        MessageError(SmallBox::from(message))
+    }
+}
+
+#[cfg(any(feature = "std", feature = "core_error"))]
+impl fmt::Debug for MessageError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(self.as_str(), f)
+    }
+}
+
+#[cfg(any(feature = "std", feature = "core_error"))]
+impl fmt::Display for MessageError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(self.as_str(), f)
+    }
+}
+
+#[cfg(any(feature = "std", feature = "core_error"))]
+impl Error for MessageError {}
+
+/// Like `Box<E>`, `SmallBox<E>` is an `Error` only for sized errors. With `E: ?Sized`,
+/// `SmallBox<dyn Error>` would be an `Error` itself, and the conversion from any error into
+/// `SmallBox<dyn Error>` would overlap with the reflexive `From<T> for T`. The `Error`
+/// methods of `SmallBox<dyn Error>` are still available through `Deref`.
+#[cfg(any(feature = "std", feature = "core_error"))]
+impl<E: Error, Space, A: Allocator> Error for SmallBox<E, Space, A> {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        (**self).source()
+    }
+}
+
+macro_rules! impl_dyn_error {
+    ($($bound:ident),*) => {
+        #[cfg(any(feature = "std", feature = "core_error"))]
+        impl<'a, E: Error $(+ $bound)* + 'a, Space> From<E> for SmallBox<dyn Error $(+ $bound)* + 'a, Space> {
+            /// Box the error on stack or on heap depending on its size.
+            #[track_caller]
+            fn from(err: E) -> Self {
+                let ptr: *const (dyn Error $(+ $bound)* + 'a) = ptr::addr_of!(err);
+                unsafe { SmallBox::new_unchecked(err, ptr) }
+            }
+        }
+
+        #[cfg(any(feature = "std", feature = "core_error"))]
+        impl<Space, A: Allocator> SmallBox<dyn Error $(+ $bound)* + 'static, Space, A> {
+            /// Attempt to downcast the box to a concrete error type.
+            #[inline]
+            pub fn downcast<T: Error + 'static>(self) -> Result<SmallBox<T, Space, A>, Self> {
+                if self.is::<T>() {
+                    unsafe { Ok(self.cast_to_unchecked()) }
+                } else {
+                    Err(self)
+                }
+            }
+        }
+    };
+}
+
+impl_dyn_error!();
+impl_dyn_error!(Send);
+impl_dyn_error!(Send, Sync);
+
+/// Signal a failed heap allocation in an infallible code path.
+#[cfg(feature = "alloc")]
+pub(crate) fn handle_alloc_error(layout: Layout) -> ! {
+    ::alloc::alloc::handle_alloc_error(layout)
+}
+
+/// Signal a failed heap allocation in an infallible code path.
+#[cfg(not(feature = "alloc"))]
+pub(crate) fn handle_alloc_error(layout: Layout) -> ! {
+    panic!("memory allocation of {} bytes failed", layout.size())
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use std::error::Error;
+    use std::fmt;
+    use std::io;
+
+    use super::*;
+    use crate::space::*;
+
+    #[derive(Debug)]
+    struct Wrapper(io::Error);
+
+    impl fmt::Display for Wrapper {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "wrapped")
+        }
+    }
+
+    impl Error for Wrapper {
+        fn source(&self) -> Option<&(dyn Error + 'static)> {
+            Some(&self.0)
+        }
+    }
+
+    fn fail() -> Result<(), io::Error> {
+        Err(io::Error::new(io::ErrorKind::Other, "io"))
+    }
+
+    fn propagate() -> Result<(), SmallBox<dyn Error + Send + Sync, S4>> {
+        fail()?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_from_error() {
+        let err = propagate().unwrap_err();
+        assert!(!err.is_heap());
+        assert_eq!(err.to_string(), "io");
+
+        let err = err.downcast::<MessageError>().unwrap_err();
+        let err = err.downcast::<io::Error>().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::Other);
+    }
+
+    #[test]
+    fn test_message_error() {
+        let short: SmallBox<dyn Error, S8> = MessageError::from("short").into();
+        assert!(!short.is_heap());
+        assert_eq!(format!("{} {:?}", short, short), "short \"short\"");
+
+        let long = MessageError::from("a message longer than the space".to_string());
+        assert_eq!(long.as_str(), "a message longer than the space");
+        let long: SmallBox<dyn Error + Send, S8> = long.into();
+        assert!(!long.is_heap());
+
+        let message = long.downcast::<MessageError>().unwrap();
+        assert_eq!(message.as_str(), "a message longer than the space");
+    }
+
+    #[test]
+    fn test_error_source() {
+        let wrapper: SmallBox<Wrapper, S1> = SmallBox::new(Wrapper(fail().unwrap_err()));
+        assert_eq!(wrapper.source().unwrap().to_string(), "io");
+
+        let err: SmallBox<dyn Error, S4> = wrapper.into_inner().into();
+        assert_eq!(err.source().unwrap().to_string(), "io");
+    }
+
+    #[test]
+    fn test_dyn_error_deref() {
+        fn describe(err: &dyn Error) -> String {
+            match err.source() {
+                Some(source) => format!("{}: {}", err, source),
+                None => err.to_string(),
+            }
+        }
+
+        let err: SmallBox<dyn Error + Send + Sync, S4> = Wrapper(fail().unwrap_err()).into();
+        assert!(err.is::<Wrapper>());
+        assert_eq!(err.source().unwrap().to_string(), "io");
+        assert_eq!(describe(&*err), "wrapped: io");
+    }
+}
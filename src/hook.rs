@@ -0,0 +1,249 @@
+//! Hook invoked whenever a value falls back to the heap
+//!
+//! `SmallBox` silently stores a value on the heap when it doesn't fit in the inline space,
+//! or when it is pinned with `SmallBox::pin` or `SmallBox::into_pin`. Register a hook
+//! with [`set_heap_hook`] to get notified about every such fallback, for example to log
+//! it, or to turn it into a panic with [`panic_on_heap`] so that tests can assert that a
+//! hot path never allocates.
+//!
+//! # Example
+//!
+//! ```
+//! use smallbox::hook;
+//! use smallbox::space::S1;
+//! use smallbox::SmallBox;
+//!
+//! hook::set_heap_hook(hook::panic_on_heap);
+//!
+//! // fits in the space, so the hook is not invoked
+//! let stacked: SmallBox<_, S1> = SmallBox::new(0usize);
+//!
+//! hook::clear_heap_hook();
+//! ```
+
+use core::alloc::Layout;
+use core::fmt;
+use core::mem;
+use core::panic::Location;
+use core::ptr;
+use core::sync::atomic::AtomicPtr;
+use core::sync::atomic::Ordering;
+
+static HEAP_HOOK: AtomicPtr<()> = AtomicPtr::new(ptr::null_mut());
+
+/// Describes a value that is about to be stored on the heap, because it doesn't fit in the
+/// inline space or because it is pinned.
+#[derive(Clone, Copy, Debug)]
+pub struct HeapFallback {
+    type_name: &'static str,
+    size: usize,
+    align: usize,
+    space_size: usize,
+    space_align: usize,
+    location: &'static Location<'static>,
+}
+
+impl HeapFallback {
+    /// Returns the name of the type of the value.
+    #[inline]
+    pub fn type_name(&self) -> &'static str {
+        self.type_name
+    }
+
+    /// Returns the size of the value in bytes.
+    #[inline]
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Returns the alignment of the value in bytes.
+    #[inline]
+    pub fn align(&self) -> usize {
+        self.align
+    }
+
+    /// Returns the size of the inline space in bytes.
+    #[inline]
+    pub fn space_size(&self) -> usize {
+        self.space_size
+    }
+
+    /// Returns the alignment of the inline space in bytes.
+    #[inline]
+    pub fn space_align(&self) -> usize {
+        self.space_align
+    }
+
+    /// Returns the location of the caller that constructed or resized the `SmallBox`.
+    #[inline]
+    pub fn location(&self) -> &'static Location<'static> {
+        self.location
+    }
+}
+
+impl fmt::Display for HeapFallback {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "`{}` (size {}, align {}) doesn't fit in the space (size {}, align {}) at {}",
+            self.type_name, self.size, self.align, self.space_size, self.space_align, self.location
+        )
+    }
+}
+
+/// Register a hook that is invoked whenever a value falls back to the heap.
+///
+/// The hook is global, and replaces the previously registered one. It is called
+/// before the heap allocation is made, and may panic to prevent it.
+#[allow(clippy::as_conversions)]
+pub fn set_heap_hook(hook: fn(&HeapFallback)) {
+    HEAP_HOOK.store(hook as *mut (), Ordering::Release);
+}
+
+/// Unregister the hook registered by [`set_heap_hook`], if any.
+pub fn clear_heap_hook() {
+    HEAP_HOOK.store(ptr::null_mut(), Ordering::Release);
+}
+
+/// A hook that panics on every heap fallback.
+///
+/// The panic message reports the type of the value, its layout, the layout of the
+/// inline space and the location of the caller.
+pub fn panic_on_heap(info: &HeapFallback) {
+    panic!("SmallBox fell back to the heap: {}", info)
+}
+
+#[inline]
+#[track_caller]
+pub(crate) fn heap_fallback<U: ?Sized, Space>(layout: Layout) {
+    let hook = HEAP_HOOK.load(Ordering::Acquire);
+    if hook.is_null() {
+        return;
+    }
+
+    let hook = unsafe { mem::transmute::<*mut (), fn(&HeapFallback)>(hook) };
+    hook(&HeapFallback {
+        type_name: core::any::type_name::<U>(),
+        size: layout.size(),
+        align: layout.align(),
+        space_size: mem::size_of::<Space>(),
+        space_align: mem::align_of::<Space>(),
+        location: Location::caller(),
+    });
+}
+
+#[cfg(all(test, feature = "alloc"))]
+mod tests {
+    use core::sync::atomic::AtomicUsize;
+    use core::sync::atomic::Ordering;
+
+    use ::alloc::vec;
+
+    use super::*;
+    use crate::space::*;
+    use crate::SmallBox;
+
+    static PROBED: AtomicUsize = AtomicUsize::new(0);
+
+    struct Probe(#[allow(dead_code)] [usize; 2]);
+    struct Strict(#[allow(dead_code)] [usize; 2]);
+
+    // the hook is global, so only react to the types used by these tests
+    fn test_hook(info: &HeapFallback) {
+        if info.type_name().ends_with("Probe") {
+            assert_eq!(info.size(), 2 * mem::size_of::<usize>());
+            assert_eq!(info.space_size(), mem::size_of::<usize>());
+            assert_eq!(info.location().file(), file!());
+            PROBED.fetch_add(1, Ordering::SeqCst);
+        } else if info.type_name().ends_with("Strict") || info.type_name().ends_with("Strict]") {
+            panic_on_heap(info);
+        }
+    }
+
+    #[test]
+    fn test_heap_hook() {
+        set_heap_hook(test_hook);
+
+        let stacked: SmallBox<_, S2> = SmallBox::new(Probe([0, 1]));
+        assert!(!stacked.is_heap());
+        assert_eq!(PROBED.load(Ordering::SeqCst), 0);
+
+        let heaped = stacked.resize::<S1>();
+        assert!(heaped.is_heap());
+        assert_eq!(PROBED.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "SmallBox fell back to the heap")]
+    fn test_panic_on_heap() {
+        set_heap_hook(test_hook);
+
+        let _stacked: SmallBox<_, S2> = SmallBox::new(Strict([0, 1]));
+        let _heaped: SmallBox<_, S1> = SmallBox::new(Strict([0, 1]));
+    }
+
+    #[test]
+    #[should_panic(expected = "SmallBox fell back to the heap")]
+    fn test_panic_on_heap_from_vec() {
+        set_heap_hook(test_hook);
+
+        let _stacked: SmallBox<[Strict], S2> = SmallBox::from(vec![Strict([0, 1])]);
+        let _heaped: SmallBox<[Strict], S2> = SmallBox::from(vec![Strict([0, 1]), Strict([2, 3])]);
+    }
+
+    #[test]
+    #[should_panic(expected = "SmallBox fell back to the heap")]
+    fn test_panic_on_heap_pin() {
+        set_heap_hook(test_hook);
+
+        let stacked: SmallBox<_, S2> = SmallBox::new(Strict([0, 1]));
+        let _pinned = stacked.into_pin();
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_panic_on_heap_drops_value() {
+        use std::panic::catch_unwind;
+        use std::panic::AssertUnwindSafe;
+
+        use crate::Global;
+        use crate::ThinSmallBox;
+
+        static DROPPED: AtomicUsize = AtomicUsize::new(0);
+
+        // also rejected by the hook, counting its drops
+        struct DropStrict(#[allow(dead_code)] [usize; 2]);
+
+        impl Drop for DropStrict {
+            fn drop(&mut self) {
+                DROPPED.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+
+        set_heap_hook(test_hook);
+
+        let new = catch_unwind(|| SmallBox::<_, S1>::new(DropStrict([0, 1])));
+        assert!(new.is_err());
+        assert_eq!(DROPPED.load(Ordering::SeqCst), 1);
+
+        let stacked: SmallBox<_, S2> = SmallBox::new(DropStrict([0, 1]));
+        assert!(catch_unwind(AssertUnwindSafe(|| stacked.resize::<S1>())).is_err());
+        assert_eq!(DROPPED.load(Ordering::SeqCst), 2);
+
+        let stacked: SmallBox<_, S2> = SmallBox::new(DropStrict([0, 1]));
+        let resized = catch_unwind(AssertUnwindSafe(|| stacked.resize_in::<S1, _>(Global)));
+        assert!(resized.is_err());
+        assert_eq!(DROPPED.load(Ordering::SeqCst), 3);
+
+        let stacked: SmallBox<_, S2> = SmallBox::new(DropStrict([0, 1]));
+        assert!(catch_unwind(AssertUnwindSafe(|| stacked.into_pin())).is_err());
+        assert_eq!(DROPPED.load(Ordering::SeqCst), 4);
+
+        assert!(catch_unwind(|| SmallBox::<_, S2>::pin(DropStrict([0, 1]))).is_err());
+        assert_eq!(DROPPED.load(Ordering::SeqCst), 5);
+
+        let thin = catch_unwind(|| ThinSmallBox::<_, S1>::new(DropStrict([0, 1])));
+        assert!(thin.is_err());
+        assert_eq!(DROPPED.load(Ordering::SeqCst), 6);
+    }
+}
@@ -0,0 +1,146 @@
+use std::fmt;
+use std::io;
+use std::io::BufRead;
+use std::io::IoSlice;
+use std::io::IoSliceMut;
+use std::io::Read;
+use std::io::Seek;
+use std::io::SeekFrom;
+use std::io::Write;
+use std::string::String;
+use std::vec::Vec;
+
+use crate::Allocator;
+use crate::SmallBox;
+
+impl<R: ?Sized + Read, Space, A: Allocator> Read for SmallBox<R, Space, A> {
+    #[inline]
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        (**self).read(buf)
+    }
+
+    #[inline]
+    fn read_vectored(&mut self, bufs: &mut [IoSliceMut<'_>]) -> io::Result<usize> {
+        (**self).read_vectored(bufs)
+    }
+
+    #[inline]
+    fn read_to_end(&mut self, buf: &mut Vec<u8>) -> io::Result<usize> {
+        (**self).read_to_end(buf)
+    }
+
+    #[inline]
+    fn read_to_string(&mut self, buf: &mut String) -> io::Result<usize> {
+        (**self).read_to_string(buf)
+    }
+
+    #[inline]
+    fn read_exact(&mut self, buf: &mut [u8]) -> io::Result<()> {
+        (**self).read_exact(buf)
+    }
+}
+
+impl<W: ?Sized + Write, Space, A: Allocator> Write for SmallBox<W, Space, A> {
+    #[inline]
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        (**self).write(buf)
+    }
+
+    #[inline]
+    fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> io::Result<usize> {
+        (**self).write_vectored(bufs)
+    }
+
+    #[inline]
+    fn flush(&mut self) -> io::Result<()> {
+        (**self).flush()
+    }
+
+    #[inline]
+    fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+        (**self).write_all(buf)
+    }
+
+    #[inline]
+    fn write_fmt(&mut self, fmt: fmt::Arguments<'_>) -> io::Result<()> {
+        (**self).write_fmt(fmt)
+    }
+}
+
+impl<B: ?Sized + BufRead, Space, A: Allocator> BufRead for SmallBox<B, Space, A> {
+    #[inline]
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        (**self).fill_buf()
+    }
+
+    #[inline]
+    fn consume(&mut self, amt: usize) {
+        (**self).consume(amt)
+    }
+
+    #[inline]
+    fn read_until(&mut self, byte: u8, buf: &mut Vec<u8>) -> io::Result<usize> {
+        (**self).read_until(byte, buf)
+    }
+
+    #[inline]
+    fn read_line(&mut self, buf: &mut String) -> io::Result<usize> {
+        (**self).read_line(buf)
+    }
+}
+
+impl<S: ?Sized + Seek, Space, A: Allocator> Seek for SmallBox<S, Space, A> {
+    #[inline]
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        (**self).seek(pos)
+    }
+
+    #[inline]
+    fn stream_position(&mut self) -> io::Result<u64> {
+        (**self).stream_position()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::space::*;
+
+    #[test]
+    fn test_read() {
+        let mut reader: SmallBox<dyn BufRead + Send, S4> = crate::smallbox!(&b"first\nsecond"[..]);
+        assert!(!reader.is_heap());
+
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        assert_eq!(line, "first\n");
+
+        let mut buf = [0; 3];
+        reader.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"sec");
+
+        let mut rest = String::new();
+        reader.read_to_string(&mut rest).unwrap();
+        assert_eq!(rest, "ond");
+    }
+
+    #[test]
+    fn test_write_seek() {
+        let mut writer: SmallBox<Cursor<Vec<u8>>, S4> = SmallBox::new(Cursor::new(Vec::new()));
+        assert!(!writer.is_heap());
+
+        write!(writer, "{}-{}", 1, 2).unwrap();
+        let written = writer
+            .write_vectored(&[IoSlice::new(b"ab"), IoSlice::new(b"cd")])
+            .unwrap();
+        assert_eq!(written, 4);
+        assert_eq!(writer.stream_position().unwrap(), 7);
+
+        writer.seek(SeekFrom::Start(1)).unwrap();
+        writer.write_all(b"+").unwrap();
+        writer.flush().unwrap();
+        assert_eq!(writer.get_ref(), b"1+2abcd");
+    }
+}
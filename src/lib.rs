@@ -32,6 +32,11 @@
 //! default-features = false
 //! ```
 //!
+//! On targets without a global allocator, leave out the `alloc` feature as well.
+//! Values that don't fit in the space are then rejected at compile time, and the APIs
+//! that may always need the heap, such as `SmallBox::pin`, are left out, unless a custom
+//! allocator is passed to their `_in` variants.
+//!
 //!
 //! # Feature Flags
 //!
@@ -40,14 +45,31 @@
 //! - `std`
 //!   - Optional, enabled by default
 //!   - Use libstd
-//!   - If `std` feature flag is opted out, `alloc` crate
-//!     will be linked, which requires nightly rust.
+//!   - Enables `alloc`
+//!
+//! - `alloc`
+//!   - Optional, enabled by default through `std`
+//!   - Links the `alloc` crate to back the heap fallback with the global allocator
+//!   - If opted out, `SmallBox::new` and `smallbox!()` refuse values that don't fit in the
+//!     space at compile time, and the fallible constructors `SmallBox::try_new` and
+//!     `try_smallbox!()` are not available. Custom allocators can still be used with
+//!     `SmallBox::new_in`.
 //!
 //! - `coerce`
 //!   - Optional
 //!   - Require nightly rust
 //!   - Allow automatic coersion from sized `SmallBox` to unsized `SmallBox`.
 //!
+//! - `stats`
+//!   - Optional
+//!   - Counts the values stored inline and on the heap, see the `stats` module
+//!   - Makes `SmallBox` one word larger
+//!
+//! - `core_error`
+//!   - Optional
+//!   - Requires rust 1.81+
+//!   - Implements `core::error::Error` without `std`, for `SmallBox` and `MessageError`
+//!
 //! - `nightly`
 //!   - Optional
 //!   - Enables `coerce`
@@ -63,11 +85,24 @@
 //!
 //! Once the feature `coerce` is enabled, sized `SmallBox<T>` can be coerced into `SmallBox<T: ?Sized>` if necessary.
 //!
+//! On stable rust, an existing sized `SmallBox` can be unsized with the `smallbox_coerce!()` macro,
+//! which keeps the value in place and only replaces the pointer metadata.
+//!
+//! On rust 1.86+, the same macro converts a trait object into a trait object of one of its
+//! supertraits, such as `dyn Any`, likewise only replacing the vtable. `SmallBox::downcast_dyn`
+//! downcasts trait objects of any trait with an `Any` supertrait.
+//!
+//! Slices and string slices of runtime length are built with `From`, from a `Vec`, `String`,
+//! slice or `&str`, or by collecting an iterator. Short data is stored inline and longer
+//! data falls back to the heap, so `SmallBox<str, S4>` doubles as a small string type.
+//!
 //! # Example
 //!
 //! Eliminate heap alloction for small items by `SmallBox`:
 //!
 //! ```rust
+//! # #[cfg(feature = "alloc")]
+//! # {
 //! use smallbox::space::S4;
 //! use smallbox::SmallBox;
 //!
@@ -82,6 +117,7 @@
 //!
 //! assert!(small.is_heap() == false);
 //! assert!(large.is_heap() == true);
+//! # }
 //! ```
 //!
 //! ## Unsized type
@@ -149,20 +185,106 @@
 //! The crate provides some spaces in module `smallbox::space`,
 //! from `S1`, `S2`, `S4` to `S64`, representing `"n * usize"` spaces.
 //!
+//! Capacities in between are expressed exactly with the const-generic spaces `Words<N>`
+//! for `N * usize`, `Bytes<N>` for `N` bytes aligned to a single byte, and
+//! `AlignedBytes<N, ALIGN>`, e.g. `AlignedBytes<24, 8>` for a 3-word struct.
+//! `SmallBoxN<T, N>` is a shorthand for `SmallBox<T, Words<N>>`.
+//!
 //! Anyway, you can defind your own space type
 //! such as byte array `[u8; 64]`.
 //! Please note that the space alignment is also important. If the alignment
 //! of the space is smaller than the alignment of the value, the value
 //! will be stored in the heap.
+//!
+//! An inline value moves together with the `SmallBox`, so it can't be aligned within the
+//! space at runtime. To store over-aligned values such as SIMD vectors inline, raise the
+//! alignment of the space with `Aligned`, e.g. `Aligned<A16, S4>` is a 16-byte aligned `S4`.
+//!
+//! # Allocator
+//!
+//! Values that don't fit in the space are stored in memory obtained from the
+//! allocator type parameter `A` of `SmallBox<T, Space, A>`, which defaults to
+//! [`Global`], the global allocator. Any type implementing the [`Allocator`] trait,
+//! such as an arena or a tracking allocator, can be used with `SmallBox::new_in`.
+//!
+//! # Inline-only Box
+//!
+//! [`StackBox`] is a sibling of `SmallBox` without the heap fallback. A value that
+//! doesn't fit in the size or the alignment of the space is a compile-time error
+//! for `StackBox::new` and `stackbox!()`, and is handed back by `StackBox::try_new`.
+//! A `StackBox` can always be converted into a `SmallBox` without allocating.
+//!
+//! # Compact Box
+//!
+//! [`CompactBox`] is a sibling of `SmallBox` for sized values only. Whether a value is stored
+//! inline follows from its type, so there is no separate pointer: a value on the heap
+//! keeps its pointer in the space. `CompactBox<u64, S1>` is a single word, which adds
+//! up for large arrays of small boxes.
+//!
+//! # Thin Box
+//!
+//! [`ThinSmallBox`] stores the pointer metadata of a trait object or slice as a header next to
+//! the value, in the space or in the heap block, so the box is only one word larger than
+//! the space. It is built with `ThinSmallBox::new` or the `thin_smallbox!()` macro.
+//!
+//! # Boxed Closures
+//!
+//! `SmallBox<dyn FnOnce()>` can't be called on stable rust. [`SmallFnOnce`] boxes a
+//! `FnOnce` closure and calls it exactly once by value, freeing the heap memory afterwards.
+//! [`SmallFnMut`] and [`SmallFn`] are the `FnMut` and `Fn` counterparts.
+//! The closures may borrow local data, and [`SendSmallFnOnce`], [`SendSmallFnMut`] and
+//! [`SendSmallFn`] box `Send` closures, which can be moved to other threads.
+//!
+//! # Heap Fallback Hook
+//!
+//! To catch values that silently outgrow their space, register a hook with
+//! [`hook::set_heap_hook`]. It is invoked with the type name, the layout of the value
+//! and of the space, and the caller location whenever a value falls back to the heap.
+//! The provided [`hook::panic_on_heap`] turns every fallback into a panic.
 #![cfg_attr(feature = "nightly", feature(strict_provenance, set_ptr_value))]
 #![cfg_attr(feature = "coerce", feature(unsize, coerce_unsized))]
 #![cfg_attr(not(feature = "std"), no_std)]
 #![deny(clippy::as_conversions)]
 
+#[cfg(feature = "alloc")]
 extern crate alloc;
 
+mod allocator;
+mod clone;
+mod compactbox;
+mod error;
+pub mod hook;
+#[cfg(feature = "std")]
+mod io;
 mod smallbox;
+mod smallfn;
 pub mod space;
 mod sptr;
+mod stackbox;
+#[cfg(feature = "stats")]
+pub mod stats;
+mod thinsmallbox;
 
+pub use crate::allocator::Allocator;
+pub use crate::allocator::Global;
+pub use crate::clone::SmallClone;
+pub use crate::compactbox::CompactBox;
+pub use crate::error::AllocError;
+#[cfg(any(feature = "std", feature = "core_error"))]
+pub use crate::error::MessageError;
 pub use crate::smallbox::SmallBox;
+pub use crate::smallbox::SmallBoxN;
+pub use crate::smallfn::CallFn;
+pub use crate::smallfn::CallMut;
+pub use crate::smallfn::CallOnce;
+pub use crate::smallfn::SendSmallFn;
+pub use crate::smallfn::SendSmallFnMut;
+pub use crate::smallfn::SendSmallFnOnce;
+pub use crate::smallfn::SmallFn;
+pub use crate::smallfn::SmallFnMut;
+pub use crate::smallfn::SmallFnOnce;
+#[doc(hidden)]
+pub use crate::sptr::dangling_of as __dangling_of;
+pub use crate::stackbox::StackBox;
+pub use crate::stackbox::StackBoxN;
+pub use crate::thinsmallbox::ThinSmallBox;
@@ -1,8 +1,15 @@
+use core::alloc::Layout;
 use core::any::Any;
+use core::any::TypeId;
+use core::borrow::Borrow;
+use core::borrow::BorrowMut;
 use core::cmp::Ordering;
 use core::fmt;
+use core::future::Future;
 use core::hash::Hash;
 use core::hash::{self};
+use core::iter::FromIterator;
+use core::iter::FusedIterator;
 use core::marker::PhantomData;
 #[cfg(feature = "coerce")]
 use core::marker::Unsize;
@@ -12,16 +19,40 @@ use core::mem::{self};
 use core::ops;
 #[cfg(feature = "coerce")]
 use core::ops::CoerceUnsized;
+use core::panic::RefUnwindSafe;
+use core::panic::UnwindSafe;
+use core::pin::Pin;
 use core::ptr;
-
-use ::alloc::alloc;
-use ::alloc::alloc::Layout;
-
+use core::ptr::NonNull;
+use core::task::Context;
+use core::task::Poll;
+
+#[cfg(feature = "alloc")]
+use ::alloc::boxed::Box;
+#[cfg(feature = "alloc")]
+use ::alloc::string::String;
+#[cfg(feature = "alloc")]
+use ::alloc::vec::Vec;
+
+use crate::clone::PartialSlice;
+use crate::clone::{self};
+use crate::error::handle_alloc_error;
+use crate::hook;
+use crate::space::Words;
 use crate::sptr;
+use crate::stackbox;
+#[cfg(feature = "stats")]
+use crate::stats::EventKind;
+#[cfg(feature = "stats")]
+use crate::stats::{self};
+use crate::AllocError;
+use crate::Allocator;
+use crate::Global;
+use crate::SmallClone;
 
 #[cfg(feature = "coerce")]
-impl<T: ?Sized + Unsize<U>, U: ?Sized, Space> CoerceUnsized<SmallBox<U, Space>>
-    for SmallBox<T, Space>
+impl<T: ?Sized + Unsize<U>, U: ?Sized, Space, A: Allocator> CoerceUnsized<SmallBox<U, Space, A>>
+    for SmallBox<T, Space, A>
 {
 }
 
@@ -41,6 +72,8 @@ impl<T: ?Sized + Unsize<U>, U: ?Sized, Space> CoerceUnsized<SmallBox<U, Space>>
 /// extern crate smallbox;
 ///
 /// # fn main() {
+/// # #[cfg(feature = "alloc")]
+/// # {
 /// use smallbox::space::*;
 /// use smallbox::SmallBox;
 ///
@@ -52,6 +85,7 @@ impl<T: ?Sized + Unsize<U>, U: ?Sized, Space> CoerceUnsized<SmallBox<U, Space>>
 ///
 /// assert!(large.is_heap() == true);
 /// # }
+/// # }
 /// ```
 #[macro_export]
 macro_rules! smallbox {
@@ -65,19 +99,167 @@ macro_rules! smallbox {
     }};
 }
 
+/// Box value on stack or on heap depending on its size, reporting allocation failure
+///
+/// This macro is the fallible version of `smallbox!`. Instead of aborting when the value
+/// has to fall back to the heap and the allocation fails, it evaluates to
+/// `Result<SmallBox<T, Space>, AllocError>`. The value is dropped on failure.
+///
+/// The target type can't be inferred through a method call on the `Result`,
+/// so annotate the `Result` itself or return it from a function.
+///
+/// # Example
+///
+/// ```
+/// #[macro_use]
+/// extern crate smallbox;
+///
+/// # fn main() {
+/// use smallbox::space::*;
+/// use smallbox::AllocError;
+/// use smallbox::SmallBox;
+///
+/// fn try_boxed_slice(val: [usize; 8]) -> Result<SmallBox<[usize], S4>, AllocError> {
+///     try_smallbox!(val)
+/// }
+///
+/// let large = try_boxed_slice([1usize; 8]).unwrap();
+///
+/// assert_eq!(large[7], 1);
+/// assert!(large.is_heap() == true);
+/// # }
+/// ```
+#[cfg(feature = "alloc")]
+#[macro_export]
+macro_rules! try_smallbox {
+    ( $e: expr ) => {{
+        let val = $e;
+        let ptr = ::core::ptr::addr_of!(val);
+        #[allow(unsafe_code)]
+        unsafe {
+            $crate::SmallBox::try_new_unchecked(val, ptr)
+        }
+    }};
+}
+
+/// Box the value returned by a closure on stack or on heap depending on its size,
+/// constructing it in place
+///
+/// This macro is similar to `SmallBox::new_with`, but relaxing the constraint `T: Sized`
+/// in the same way as `smallbox!()` does. The closure is called after the storage is
+/// chosen, and its return value is written directly to it.
+///
+/// You can think that it has the signature of
+/// `smallbox_with!<U: Sized, T: ?Sized>(f: impl FnOnce() -> U) -> SmallBox<T, Space>`
+///
+/// # Example
+///
+/// ```
+/// #[macro_use]
+/// extern crate smallbox;
+///
+/// # fn main() {
+/// use smallbox::space::*;
+/// use smallbox::SmallBox;
+///
+/// let array: SmallBox<[usize], S4> = smallbox_with!(|| [1usize; 4]);
+///
+/// assert_eq!(array.len(), 4);
+/// assert!(!array.is_heap());
+/// # }
+/// ```
+#[macro_export]
+macro_rules! smallbox_with {
+    ( $f: expr ) => {{
+        let f = $f;
+        let ptr = $crate::__dangling_of(&f);
+        #[allow(unsafe_code)]
+        unsafe {
+            $crate::SmallBox::new_with_unchecked(f, ptr)
+        }
+    }};
+}
+
+/// Convert a `SmallBox<T, Space>` into a `SmallBox<U, Space>` by an unsizing coercion,
+/// without moving the value
+///
+/// This is the stable counterpart of the coercion enabled by the `coerce` feature. The
+/// macro checks that `T` can be unsized to `U`, and invokes a compile-time error otherwise.
+///
+/// On rust 1.86+, where trait objects coerce to their supertraits, this also upcasts a
+/// `SmallBox<dyn Sub, Space>` into a `SmallBox<dyn Super, Space>`, replacing only the vtable.
+///
+/// You can think that it has the signature of
+/// `smallbox_coerce!<T, U: ?Sized>(boxed: SmallBox<T, Space, A>) -> SmallBox<U, Space, A>`
+///
+/// # Example
+///
+/// ```
+/// #[macro_use]
+/// extern crate smallbox;
+///
+/// # fn main() {
+/// use core::any::Any;
+///
+/// use smallbox::space::*;
+/// use smallbox::SmallBox;
+///
+/// let num: SmallBox<usize, S1> = SmallBox::new(42usize);
+/// let any: SmallBox<dyn Any, S1> = smallbox_coerce!(num);
+///
+/// assert_eq!(any.downcast_ref::<usize>(), Some(&42));
+/// # }
+/// ```
+#[macro_export]
+macro_rules! smallbox_coerce {
+    ( $e: expr ) => {{
+        let boxed = $e;
+        let ptr = $crate::SmallBox::metadata_ptr(&boxed);
+        #[allow(unsafe_code)]
+        unsafe {
+            $crate::SmallBox::coerce_unchecked(boxed, ptr)
+        }
+    }};
+}
+
 /// An optimized box that store value on stack or on heap depending on its size
-pub struct SmallBox<T: ?Sized, Space> {
+///
+/// Values that don't fit in the inline space are stored in memory obtained from the
+/// allocator `A`, which defaults to the global allocator.
+///
+/// Unlike `Box`, `SmallBox<T>` is only `Unpin` if `T` is, because a value stored inline
+/// moves along with the box.
+pub struct SmallBox<T: ?Sized, Space, A: Allocator = Global> {
     space: MaybeUninit<Space>,
-    ptr: *mut T,
+    ptr: NonNull<T>,
+    alloc: A,
+    // records events under the type and space the value was placed with, which coercions
+    // and downcasts change, so that its free is recorded under the same key
+    #[cfg(feature = "stats")]
+    record: fn(EventKind, Layout),
     _phantom: PhantomData<T>,
 }
 
+/// A `SmallBox` with a space of `N * usize`
+///
+/// # Example
+///
+/// ```
+/// use smallbox::SmallBoxN;
+///
+/// let stacked: SmallBoxN<(usize, usize, usize), 3> = SmallBoxN::new((1, 2, 3));
+/// assert!(!stacked.is_heap());
+/// ```
+pub type SmallBoxN<T, const N: usize> = SmallBox<T, Words<N>>;
+
 impl<T: ?Sized, Space> SmallBox<T, Space> {
     /// Box value on stack or on heap depending on its size.
     ///
     /// # Example
     ///
     /// ```
+    /// # #[cfg(feature = "alloc")]
+    /// # {
     /// use smallbox::space::*;
     /// use smallbox::SmallBox;
     ///
@@ -88,8 +270,10 @@ impl<T: ?Sized, Space> SmallBox<T, Space> {
     /// assert_eq!(large[7], 1);
     ///
     /// assert!(large.is_heap() == true);
+    /// # }
     /// ```
     #[inline(always)]
+    #[track_caller]
     pub fn new(val: T) -> SmallBox<T, Space>
     where T: Sized {
         smallbox!(val)
@@ -97,497 +281,2876 @@ impl<T: ?Sized, Space> SmallBox<T, Space> {
 
     #[doc(hidden)]
     #[inline]
+    #[track_caller]
     pub unsafe fn new_unchecked<U>(val: U, ptr: *const T) -> SmallBox<T, Space>
     where U: Sized {
-        let val = ManuallyDrop::new(val);
-        Self::new_copy(&val, ptr)
+        Self::new_unchecked_in(val, ptr, Global)
     }
 
-    /// Change the capacity of `SmallBox`.
+    /// Box the value returned by `f` on stack or on heap depending on its size,
+    /// constructing it in place.
     ///
-    /// This method may move stack-allocated data from stack to heap
-    /// when inline space is not sufficient. And once the data
-    /// is moved to heap, it'll never be moved again.
+    /// The storage is chosen before `f` is called, and the return value of `f`
+    /// is written directly to it, which saves the intermediate copies of `new`
+    /// for large values. If `f` panics, the heap memory, if any, is freed.
     ///
     /// # Example
     ///
     /// ```
-    /// use smallbox::space::S2;
-    /// use smallbox::space::S4;
+    /// # #[cfg(feature = "alloc")]
+    /// # {
+    /// use smallbox::space::*;
     /// use smallbox::SmallBox;
     ///
-    /// let s: SmallBox<_, S4> = SmallBox::new([0usize; 4]);
-    /// let m: SmallBox<_, S2> = s.resize();
+    /// let large: SmallBox<_, S4> = SmallBox::new_with(|| [1u8; 4096]);
+    ///
+    /// assert_eq!(large[4095], 1);
+    /// assert!(large.is_heap() == true);
+    /// # }
     /// ```
-    pub fn resize<ToSpace>(self) -> SmallBox<T, ToSpace> {
-        let this = ManuallyDrop::new(self);
+    #[inline(always)]
+    #[track_caller]
+    pub fn new_with<F>(f: F) -> SmallBox<T, Space>
+    where
+        T: Sized,
+        F: FnOnce() -> T,
+    {
+        smallbox_with!(f)
+    }
 
-        if this.is_heap() {
-            // don't change anything if data is already on heap
-            let space = MaybeUninit::<ToSpace>::uninit();
-            SmallBox {
-                space,
-                ptr: this.ptr,
-                _phantom: PhantomData,
-            }
-        } else {
-            let val: &T = &this;
-            unsafe { SmallBox::<T, ToSpace>::new_copy(val, sptr::from_ref(val)) }
-        }
+    #[doc(hidden)]
+    #[inline]
+    #[track_caller]
+    pub unsafe fn new_with_unchecked<U, F>(f: F, ptr: *const T) -> SmallBox<T, Space>
+    where F: FnOnce() -> U {
+        Self::new_with_unchecked_in(f, ptr, Global)
     }
 
-    /// Returns true if data is allocated on heap.
+    /// Box a value on stack or on heap depending on its size, letting `init`
+    /// initialize it in place.
+    ///
+    /// # Safety
+    ///
+    /// `init` must fully initialize the value before it returns. If `init` panics,
+    /// the heap memory, if any, is freed and the value is not dropped.
     ///
     /// # Example
     ///
     /// ```
-    /// use smallbox::space::S1;
+    /// use core::mem::MaybeUninit;
+    ///
+    /// use smallbox::space::*;
     /// use smallbox::SmallBox;
     ///
-    /// let stacked: SmallBox<usize, S1> = SmallBox::new(0usize);
-    /// assert!(!stacked.is_heap());
+    /// let boxed: SmallBox<[usize; 2], S2> = unsafe {
+    ///     SmallBox::emplace(|slot: &mut MaybeUninit<[usize; 2]>| {
+    ///         slot.as_mut_ptr().write([1, 2]);
+    ///     })
+    /// };
     ///
-    /// let heaped: SmallBox<(usize, usize), S1> = SmallBox::new((0usize, 1usize));
-    /// assert!(heaped.is_heap());
+    /// assert_eq!(*boxed, [1, 2]);
     /// ```
     #[inline]
-    pub fn is_heap(&self) -> bool {
-        !self.ptr.is_null()
-    }
-
-    unsafe fn new_copy<U>(val: &U, metadata_ptr: *const T) -> SmallBox<T, Space>
-    where U: ?Sized {
-        let size = mem::size_of_val::<U>(val);
-        let align = mem::align_of_val::<U>(val);
-
-        let mut space = MaybeUninit::<Space>::uninit();
-
-        let (ptr_this, val_dst): (*mut u8, *mut u8) = if size == 0 {
-            (ptr::null_mut(), sptr::without_provenance_mut(align))
-        } else if size > mem::size_of::<Space>() || align > mem::align_of::<Space>() {
-            // Heap
-            let layout = Layout::for_value::<U>(val);
-            let heap_ptr = alloc::alloc(layout);
-
-            (heap_ptr, heap_ptr)
-        } else {
-            // Stack
-            (ptr::null_mut(), space.as_mut_ptr().cast())
-        };
-
-        // `self.ptr` always holds the metadata, even if stack allocated
-        let ptr = sptr::with_metadata_of_mut(ptr_this, metadata_ptr);
-
-        ptr::copy_nonoverlapping(sptr::from_ref(val).cast(), val_dst, size);
-
-        SmallBox {
-            space,
-            ptr,
-            _phantom: PhantomData,
-        }
+    #[track_caller]
+    pub unsafe fn emplace<F>(init: F) -> SmallBox<T, Space>
+    where
+        T: Sized,
+        F: FnOnce(&mut MaybeUninit<T>),
+    {
+        Self::emplace_in(init, Global)
     }
 
-    unsafe fn downcast_unchecked<U: Any>(self) -> SmallBox<U, Space> {
-        let size = mem::size_of::<U>();
-        let mut space = MaybeUninit::<Space>::uninit();
-
-        if !self.is_heap() {
-            ptr::copy_nonoverlapping::<u8>(
-                self.space.as_ptr().cast(),
-                space.as_mut_ptr().cast(),
-                size,
-            );
-        };
-
-        let ptr = self.ptr.cast();
-
-        mem::forget(self);
-
-        SmallBox {
-            space,
-            ptr,
-            _phantom: PhantomData,
-        }
+    /// Box value on stack or on heap depending on its size, returning an error
+    /// if the heap allocation fails.
+    ///
+    /// The value is dropped if the allocation fails.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use smallbox::space::*;
+    /// use smallbox::SmallBox;
+    ///
+    /// let small: SmallBox<_, S4> = SmallBox::try_new([0usize; 2]).unwrap();
+    /// let large: SmallBox<_, S4> = SmallBox::try_new([1usize; 8]).unwrap();
+    ///
+    /// assert_eq!(small.len(), 2);
+    /// assert_eq!(large[7], 1);
+    ///
+    /// assert!(large.is_heap() == true);
+    /// ```
+    #[cfg(feature = "alloc")]
+    #[inline(always)]
+    #[track_caller]
+    pub fn try_new(val: T) -> Result<SmallBox<T, Space>, AllocError>
+    where T: Sized {
+        try_smallbox!(val)
     }
 
+    #[cfg(feature = "alloc")]
+    #[doc(hidden)]
     #[inline]
-    unsafe fn as_ptr(&self) -> *const T {
-        if self.is_heap() {
-            self.ptr
-        } else {
-            sptr::with_metadata_of(self.space.as_ptr(), self.ptr)
-        }
+    #[track_caller]
+    pub unsafe fn try_new_unchecked<U>(
+        val: U,
+        ptr: *const T,
+    ) -> Result<SmallBox<T, Space>, AllocError>
+    where
+        U: Sized,
+    {
+        Self::try_new_unchecked_in(val, ptr, Global)
     }
 
+    /// Box value on heap regardless of its size, and pin it.
+    ///
+    /// Values stored inline move whenever the `SmallBox` moves, so a pinned
+    /// value always lives on the heap. `Unpin` values don't need this and can
+    /// be stored inline with `Pin::new(SmallBox::new(val))`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use core::pin::Pin;
+    ///
+    /// use smallbox::space::S4;
+    /// use smallbox::SmallBox;
+    ///
+    /// let fut: Pin<SmallBox<_, S4>> = SmallBox::pin(async { 42 });
+    /// ```
+    #[cfg(feature = "alloc")]
     #[inline]
-    unsafe fn as_mut_ptr(&mut self) -> *mut T {
-        if self.is_heap() {
-            self.ptr
-        } else {
-            sptr::with_metadata_of_mut(self.space.as_mut_ptr(), self.ptr)
-        }
+    #[track_caller]
+    pub fn pin(val: T) -> Pin<SmallBox<T, Space>>
+    where T: Sized {
+        Self::pin_in(val, Global)
     }
 
-    /// Consumes the SmallBox and returns ownership of the boxed value
+    /// Convert a `Box<T>` into a `SmallBox<T, Space>`, adopting its heap allocation
+    /// without copying the value.
+    ///
+    /// # Example
     ///
-    /// # Examples
     /// ```
-    /// use smallbox::space::S1;
+    /// # #[cfg(feature = "alloc")]
+    /// # {
+    /// use std::fmt::Debug;
+    ///
+    /// use smallbox::space::S4;
     /// use smallbox::SmallBox;
     ///
-    /// let stacked: SmallBox<_, S1> = SmallBox::new([21usize]);
-    /// let val = stacked.into_inner();
-    /// assert_eq!(val[0], 21);
+    /// let boxed: Box<dyn Debug> = Box::new([0usize; 2]);
+    /// let small: SmallBox<dyn Debug, S4> = SmallBox::from_box(boxed);
     ///
-    /// let boxed: SmallBox<_, S1> = SmallBox::new(vec![21, 56, 420]);
-    /// let val = boxed.into_inner();
-    /// assert_eq!(val[1], 56);
+    /// assert!(small.is_heap());
+    /// # }
     /// ```
+    #[cfg(feature = "alloc")]
     #[inline]
-    pub fn into_inner(self) -> T
-    where T: Sized {
-        let this = ManuallyDrop::new(self);
-        let ret_val: T = unsafe { this.as_ptr().read() };
+    pub fn from_box(boxed: Box<T>) -> SmallBox<T, Space> {
+        let raw = Box::into_raw(boxed);
+        let layout = Layout::for_value::<T>(unsafe { &*raw });
+
+        // zero-sized values are always kept inline, there is nothing to free
+        let ptr = if layout.size() == 0 {
+            #[cfg(feature = "stats")]
+            stats::record::<T, Space>(EventKind::Inline, layout);
+            sptr::with_metadata_of_mut(inline_ptr(), raw)
+        } else {
+            #[cfg(feature = "stats")]
+            stats::record::<T, Space>(EventKind::Heap, layout);
+            raw
+        };
 
-        // Just drops the heap without dropping the boxed value
-        if this.is_heap() {
-            let layout = Layout::new::<T>();
-            unsafe {
-                alloc::dealloc(this.ptr.cast(), layout);
-            }
+        SmallBox {
+            space: MaybeUninit::uninit(),
+            ptr: unsafe { NonNull::new_unchecked(ptr) },
+            alloc: Global,
+            #[cfg(feature = "stats")]
+            record: stats::record::<T, Space>,
+            _phantom: PhantomData,
         }
-
-        ret_val
     }
-}
 
-impl<Space> SmallBox<dyn Any, Space> {
-    /// Attempt to downcast the box to a concrete type.
+    /// Convert a `Box<T>` into a `SmallBox<T, Space>`, moving the value inline
+    /// and freeing the box if it fits in the space.
     ///
-    /// # Examples
+    /// Values that don't fit keep their heap allocation, like with [`SmallBox::from_box`].
     ///
-    /// ```
-    /// #[macro_use]
-    /// extern crate smallbox;
+    /// # Example
     ///
-    /// # fn main() {
-    /// use core::any::Any;
+    /// ```
+    /// # #[cfg(feature = "alloc")]
+    /// # {
+    /// use std::fmt::Debug;
     ///
-    /// use smallbox::space::*;
+    /// use smallbox::space::S4;
     /// use smallbox::SmallBox;
     ///
-    /// fn print_if_string(value: SmallBox<dyn Any, S1>) {
-    ///     if let Ok(string) = value.downcast::<String>() {
-    ///         println!("String ({}): {}", string.len(), string);
-    ///     }
-    /// }
+    /// let boxed: Box<dyn Debug> = Box::new([0usize; 2]);
+    /// let small: SmallBox<dyn Debug, S4> = SmallBox::from_box_inline(boxed);
     ///
-    /// fn main() {
-    ///     let my_string = "Hello World".to_string();
-    ///     print_if_string(smallbox!(my_string));
-    ///     print_if_string(smallbox!(0i8));
-    /// }
+    /// assert!(!small.is_heap());
     /// # }
     /// ```
+    #[cfg(feature = "alloc")]
     #[inline]
-    pub fn downcast<T: Any>(self) -> Result<SmallBox<T, Space>, Self> {
-        if self.is::<T>() {
-            unsafe { Ok(self.downcast_unchecked()) }
-        } else {
-            Err(self)
+    pub fn from_box_inline(boxed: Box<T>) -> SmallBox<T, Space> {
+        let layout = Layout::for_value::<T>(&*boxed);
+        if !fits_inline::<Space>(layout) {
+            return Self::from_box(boxed);
+        }
+
+        let raw = Box::into_raw(boxed);
+        unsafe {
+            let this = Self::new_copy(&*raw, raw, Global);
+            Global.deallocate(NonNull::new_unchecked(raw.cast()), layout);
+            this
         }
     }
-}
 
-impl<Space> SmallBox<dyn Any + Send, Space> {
-    /// Attempt to downcast the box to a concrete type.
+    /// Convert into a `Box<T>`, handing over the heap allocation without copying
+    /// the value.
     ///
-    /// # Examples
+    /// Only values stored inline are moved to a new heap allocation.
+    ///
+    /// # Example
     ///
     /// ```
     /// #[macro_use]
     /// extern crate smallbox;
     ///
     /// # fn main() {
-    /// use core::any::Any;
-    ///
-    /// use smallbox::space::*;
+    /// # #[cfg(feature = "alloc")]
+    /// # {
+    /// use smallbox::space::S4;
     /// use smallbox::SmallBox;
     ///
-    /// fn print_if_string(value: SmallBox<dyn Any, S1>) {
-    ///     if let Ok(string) = value.downcast::<String>() {
-    ///         println!("String ({}): {}", string.len(), string);
-    ///     }
-    /// }
+    /// let small: SmallBox<[usize], S4> = smallbox!([0usize, 1]);
+    /// let boxed: Box<[usize]> = small.into_box();
     ///
-    /// fn main() {
-    ///     let my_string = "Hello World".to_string();
-    ///     print_if_string(smallbox!(my_string));
-    ///     print_if_string(smallbox!(0i8));
-    /// }
+    /// assert_eq!(*boxed, [0, 1]);
+    /// # }
     /// # }
     /// ```
+    #[cfg(feature = "alloc")]
     #[inline]
-    pub fn downcast<T: Any>(self) -> Result<SmallBox<T, Space>, Self> {
-        if self.is::<T>() {
-            unsafe { Ok(self.downcast_unchecked()) }
-        } else {
-            Err(self)
+    pub fn into_box(self) -> Box<T> {
+        let mut this = ManuallyDrop::new(self);
+        let layout = Layout::for_value::<T>(&**this);
+
+        if this.is_heap() {
+            // the allocation is no longer owned by a `SmallBox`
+            #[cfg(feature = "stats")]
+            (this.record)(EventKind::Free, layout);
+            return unsafe { Box::from_raw(this.ptr.as_ptr()) };
+        }
+
+        let heap_ptr = match Global.allocate(layout) {
+            Ok(heap_ptr) => heap_ptr.as_ptr(),
+            Err(err) => handle_alloc_error(err.layout()),
+        };
+        unsafe {
+            ptr::copy_nonoverlapping(this.as_mut_ptr().cast::<u8>(), heap_ptr, layout.size());
+            Box::from_raw(sptr::with_metadata_of_mut(heap_ptr, this.ptr.as_ptr()))
         }
     }
 }
 
-impl<T: ?Sized, Space> ops::Deref for SmallBox<T, Space> {
+impl<T: ?Sized, Space, A: Allocator> SmallBox<T, Space, A> {
+    /// Box value on stack or on heap depending on its size, using the
+    /// given allocator for the heap fallback.
+    ///
+    /// It fails to compile if the value doesn't fit in the size or the alignment of `Space`
+    /// and `A` can't allocate, as `Global` without the `alloc` feature.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # #[cfg(feature = "alloc")]
+    /// # {
+    /// use smallbox::space::*;
+    /// use smallbox::Global;
+    /// use smallbox::SmallBox;
+    ///
+    /// let small: SmallBox<_, S4, _> = SmallBox::new_in([0usize; 2], Global);
+    /// let large: SmallBox<_, S4, _> = SmallBox::new_in([1usize; 8], Global);
+    ///
+    /// assert_eq!(small.len(), 2);
+    /// assert_eq!(large[7], 1);
+    ///
+    /// assert!(large.is_heap() == true);
+    /// # }
+    /// ```
+    #[inline(always)]
+    #[track_caller]
+    pub fn new_in(val: T, alloc: A) -> SmallBox<T, Space, A>
+    where T: Sized {
+        let ptr = ptr::addr_of!(val);
+        unsafe { Self::new_unchecked_in(val, ptr, alloc) }
+    }
+
+    #[doc(hidden)]
+    #[inline]
+    #[track_caller]
+    pub unsafe fn new_unchecked_in<U>(val: U, ptr: *const T, alloc: A) -> SmallBox<T, Space, A>
+    where U: Sized {
+        // with an allocator that can't allocate there is nowhere to spill, so refuse at compile time
+        #[allow(clippy::let_unit_value)]
+        let () = AssertCanStore::<U, Space, A>::ASSERT;
+        // `val` is only forgotten once copied, so it is dropped if the heap fallback panics
+        let this = Self::new_copy(&val, ptr, alloc);
+        mem::forget(val);
+        this
+    }
+
+    /// Box value on stack or on heap depending on its size, using the given
+    /// allocator for the heap fallback and returning an error if the heap
+    /// allocation fails.
+    ///
+    /// The value is dropped if the allocation fails.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # #[cfg(feature = "alloc")]
+    /// # {
+    /// use smallbox::space::*;
+    /// use smallbox::Global;
+    /// use smallbox::SmallBox;
+    ///
+    /// let large: SmallBox<_, S4, _> = SmallBox::try_new_in([1usize; 8], Global).unwrap();
+    ///
+    /// assert_eq!(large[7], 1);
+    /// assert!(large.is_heap() == true);
+    /// # }
+    /// ```
+    #[inline(always)]
+    #[track_caller]
+    pub fn try_new_in(val: T, alloc: A) -> Result<SmallBox<T, Space, A>, AllocError>
+    where T: Sized {
+        let ptr = ptr::addr_of!(val);
+        unsafe { Self::try_new_unchecked_in(val, ptr, alloc) }
+    }
+
+    #[doc(hidden)]
+    #[inline]
+    #[track_caller]
+    pub unsafe fn try_new_unchecked_in<U>(
+        val: U,
+        ptr: *const T,
+        alloc: A,
+    ) -> Result<SmallBox<T, Space, A>, AllocError>
+    where
+        U: Sized,
+    {
+        let this = Self::try_new_copy(&val, ptr, alloc)?;
+        mem::forget(val);
+        Ok(this)
+    }
+
+    /// Box the value returned by `f` on stack or on heap depending on its size,
+    /// using the given allocator for the heap fallback and constructing the value in place.
+    ///
+    /// See [`SmallBox::new_with`] for details.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # #[cfg(feature = "alloc")]
+    /// # {
+    /// use smallbox::space::*;
+    /// use smallbox::Global;
+    /// use smallbox::SmallBox;
+    ///
+    /// let large: SmallBox<_, S4, _> = SmallBox::new_with_in(|| [1u8; 4096], Global);
+    ///
+    /// assert_eq!(large[4095], 1);
+    /// assert!(large.is_heap() == true);
+    /// # }
+    /// ```
+    #[inline(always)]
+    #[track_caller]
+    pub fn new_with_in<F>(f: F, alloc: A) -> SmallBox<T, Space, A>
+    where
+        T: Sized,
+        F: FnOnce() -> T,
+    {
+        let ptr = sptr::dangling_of(&f);
+        unsafe { Self::new_with_unchecked_in(f, ptr, alloc) }
+    }
+
+    #[doc(hidden)]
+    #[inline]
+    #[track_caller]
+    pub unsafe fn new_with_unchecked_in<U, F>(
+        f: F,
+        ptr: *const T,
+        alloc: A,
+    ) -> SmallBox<T, Space, A>
+    where
+        F: FnOnce() -> U,
+    {
+        #[allow(clippy::let_unit_value)]
+        let () = AssertCanStore::<U, Space, A>::ASSERT;
+        let init = |dst: *mut u8| dst.cast::<U>().write(f());
+        match Self::try_new_init::<U, _>(Layout::new::<U>(), ptr, alloc, init) {
+            Ok(this) => this,
+            Err(err) => handle_alloc_error(err.layout()),
+        }
+    }
+
+    /// Box a value on stack or on heap depending on its size, using the given
+    /// allocator for the heap fallback and letting `init` initialize the value in place.
+    ///
+    /// # Safety
+    ///
+    /// See [`SmallBox::emplace`].
+    #[inline]
+    #[track_caller]
+    pub unsafe fn emplace_in<F>(init: F, alloc: A) -> SmallBox<T, Space, A>
+    where
+        T: Sized,
+        F: FnOnce(&mut MaybeUninit<T>),
+    {
+        #[allow(clippy::let_unit_value)]
+        let () = AssertCanStore::<T, Space, A>::ASSERT;
+        let ptr = NonNull::<T>::dangling().as_ptr();
+        let init = |dst: *mut u8| init(&mut *dst.cast::<MaybeUninit<T>>());
+        match Self::try_new_init::<T, _>(Layout::new::<T>(), ptr, alloc, init) {
+            Ok(this) => this,
+            Err(err) => handle_alloc_error(err.layout()),
+        }
+    }
+
+    /// Returns a reference to the underlying allocator.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use smallbox::space::S1;
+    /// use smallbox::Global;
+    /// use smallbox::SmallBox;
+    ///
+    /// let boxed: SmallBox<_, S1, _> = SmallBox::new_in(0usize, Global);
+    /// let _: &Global = boxed.allocator();
+    /// ```
+    #[inline]
+    pub fn allocator(&self) -> &A {
+        &self.alloc
+    }
+
+    /// Change the capacity of `SmallBox`.
+    ///
+    /// This method may move stack-allocated data from stack to heap
+    /// when inline space is not sufficient. And once the data
+    /// is moved to heap, it'll never be moved again.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # #[cfg(feature = "alloc")]
+    /// # {
+    /// use smallbox::space::S2;
+    /// use smallbox::space::S4;
+    /// use smallbox::SmallBox;
+    ///
+    /// let s: SmallBox<_, S4> = SmallBox::new([0usize; 4]);
+    /// let m: SmallBox<_, S2> = s.resize();
+    /// # }
+    /// ```
+    #[track_caller]
+    pub fn resize<ToSpace>(self) -> SmallBox<T, ToSpace, A> {
+        #[allow(clippy::let_unit_value)]
+        let () = AssertCanResize::<Space, ToSpace, A, A>::ASSERT;
+        if self.is_heap() {
+            return self.into_heap_space();
+        }
+
+        match self.try_resize_inline() {
+            Ok(resized) => resized,
+            Err(err) => handle_alloc_error(err.layout()),
+        }
+    }
+
+    /// Change the space of a `SmallBox` whose value is on heap, which doesn't move the value.
+    fn into_heap_space<ToSpace>(self) -> SmallBox<T, ToSpace, A> {
+        debug_assert!(self.is_heap());
+        let this = ManuallyDrop::new(self);
+        SmallBox {
+            space: MaybeUninit::uninit(),
+            ptr: this.ptr,
+            alloc: unsafe { ptr::read(&this.alloc) },
+            #[cfg(feature = "stats")]
+            record: this.record,
+            _phantom: PhantomData,
+        }
+    }
+
+    /// Change the capacity of `SmallBox`, returning an error if the data has to be
+    /// moved to heap and the allocation fails.
+    ///
+    /// The boxed value is dropped if the allocation fails.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # #[cfg(feature = "alloc")]
+    /// # {
+    /// use smallbox::space::S2;
+    /// use smallbox::space::S4;
+    /// use smallbox::SmallBox;
+    ///
+    /// let s: SmallBox<_, S4> = SmallBox::new([0usize; 4]);
+    /// let m: SmallBox<_, S2> = s.try_resize().unwrap();
+    /// # }
+    /// ```
+    #[track_caller]
+    pub fn try_resize<ToSpace>(self) -> Result<SmallBox<T, ToSpace, A>, AllocError> {
+        if self.is_heap() {
+            return Ok(self.into_heap_space());
+        }
+
+        self.try_resize_inline()
+    }
+
+    /// Move an inline value into a `SmallBox` with a different space.
+    ///
+    /// The new place is set up before anything is moved out of `self`, so the
+    /// value is dropped if the heap fallback hook panics or the allocation fails.
+    #[track_caller]
+    fn try_resize_inline<ToSpace>(self) -> Result<SmallBox<T, ToSpace, A>, AllocError> {
+        debug_assert!(!self.is_heap());
+        let layout = Layout::for_value::<T>(&*self);
+        let ptr_this = SmallBox::<T, ToSpace, A>::try_place::<T>(layout, &self.alloc, false)?;
+
+        let this = ManuallyDrop::new(self);
+        let alloc = unsafe { ptr::read(&this.alloc) };
+        let val: &T = &this;
+        unsafe {
+            Ok(SmallBox::<T, ToSpace, A>::new_at::<T, _>(
+                ptr_this,
+                layout,
+                sptr::from_ref(val),
+                alloc,
+                |dst| ptr::copy_nonoverlapping(sptr::from_ref(val).cast(), dst, layout.size()),
+            ))
+        }
+    }
+
+    /// Change the capacity and the allocator of `SmallBox`.
+    ///
+    /// The value is moved out of the old allocator, either into the inline space
+    /// if it fits, or into memory obtained from the new allocator.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # #[cfg(feature = "alloc")]
+    /// # {
+    /// use smallbox::space::S1;
+    /// use smallbox::space::S4;
+    /// use smallbox::Global;
+    /// use smallbox::SmallBox;
+    ///
+    /// let s: SmallBox<_, S1> = SmallBox::new([0usize; 4]);
+    /// assert!(s.is_heap());
+    /// let m: SmallBox<_, S4, _> = s.resize_in(Global);
+    /// assert!(!m.is_heap());
+    /// # }
+    /// ```
+    #[track_caller]
+    pub fn resize_in<ToSpace, B: Allocator>(self, alloc: B) -> SmallBox<T, ToSpace, B> {
+        #[allow(clippy::let_unit_value)]
+        let () = AssertCanResize::<Space, ToSpace, A, B>::ASSERT;
+        let layout = Layout::for_value::<T>(&*self);
+        let ptr_this = match SmallBox::<T, ToSpace, B>::try_place::<T>(layout, &alloc, false) {
+            Ok(ptr_this) => ptr_this,
+            Err(err) => handle_alloc_error(err.layout()),
+        };
+
+        let this = ManuallyDrop::new(self);
+        let val: &T = &this;
+        let resized = unsafe {
+            SmallBox::<T, ToSpace, B>::new_at::<T, _>(
+                ptr_this,
+                layout,
+                sptr::from_ref(val),
+                alloc,
+                |dst| ptr::copy_nonoverlapping(sptr::from_ref(val).cast(), dst, layout.size()),
+            )
+        };
+        // the value has been moved out, just free the old storage
+        unsafe { ManuallyDrop::into_inner(this).dealloc_without_drop() };
+        resized
+    }
+
+    /// Returns true if data is allocated on heap.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # #[cfg(feature = "alloc")]
+    /// # {
+    /// use smallbox::space::S1;
+    /// use smallbox::SmallBox;
+    ///
+    /// let stacked: SmallBox<usize, S1> = SmallBox::new(0usize);
+    /// assert!(!stacked.is_heap());
+    ///
+    /// let heaped: SmallBox<(usize, usize), S1> = SmallBox::new((0usize, 1usize));
+    /// assert!(heaped.is_heap());
+    /// # }
+    /// ```
+    #[inline]
+    pub fn is_heap(&self) -> bool {
+        self.ptr.cast::<u8>().as_ptr() != inline_ptr()
+    }
+
+    /// Box value on heap regardless of its size, and pin it, using the given allocator.
+    ///
+    /// Values stored inline move whenever the `SmallBox` moves, so a pinned
+    /// value always lives on the heap. Zero-sized values take no memory there,
+    /// the allocator hands out a dangling pointer that doesn't move either.
+    #[inline]
+    #[track_caller]
+    pub fn pin_in(val: T, alloc: A) -> Pin<SmallBox<T, Space, A>>
+    where T: Sized {
+        // a pinned value is always stored on heap
+        #[allow(clippy::let_unit_value)]
+        let () = AssertCanStore::<T, (), A>::ASSERT;
+        let layout = Layout::new::<T>();
+        let ptr_this = match Self::try_place::<T>(layout, &alloc, true) {
+            Ok(ptr_this) => ptr_this,
+            Err(err) => handle_alloc_error(err.layout()),
+        };
+
+        let val = ManuallyDrop::new(val);
+        let this = unsafe {
+            Self::new_at::<T, _>(ptr_this, layout, sptr::from_ref(&*val), alloc, |dst| {
+                ptr::copy_nonoverlapping(sptr::from_ref(&*val).cast(), dst, layout.size())
+            })
+        };
+        unsafe { Pin::new_unchecked(this) }
+    }
+
+    /// Convert into a `Pin<SmallBox<T, Space>>`, moving the value to the heap
+    /// if it is stored inline.
+    ///
+    /// Values stored inline move whenever the `SmallBox` moves, so a pinned
+    /// value always lives on the heap. Zero-sized values take no memory there,
+    /// the allocator hands out a dangling pointer that doesn't move either.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// #[macro_use]
+    /// extern crate smallbox;
+    ///
+    /// # fn main() {
+    /// # #[cfg(feature = "alloc")]
+    /// # {
+    /// use core::future::Future;
+    ///
+    /// use smallbox::space::S4;
+    /// use smallbox::SmallBox;
+    ///
+    /// let fut: SmallBox<dyn Future<Output = usize>, S4> = smallbox!(async { 42 });
+    /// let pinned = fut.into_pin();
+    /// # }
+    /// # }
+    /// ```
+    #[inline]
+    #[track_caller]
+    pub fn into_pin(self) -> Pin<SmallBox<T, Space, A>> {
+        #[allow(clippy::let_unit_value)]
+        let () = AssertCanAllocate::<A>::ASSERT;
+        if self.is_heap() {
+            return unsafe { Pin::new_unchecked(self) };
+        }
+
+        let layout = Layout::for_value::<T>(&*self);
+        let ptr_this = match Self::try_place::<T>(layout, &self.alloc, true) {
+            Ok(ptr_this) => ptr_this,
+            Err(err) => handle_alloc_error(err.layout()),
+        };
+
+        let this = ManuallyDrop::new(self);
+        let alloc = unsafe { ptr::read(&this.alloc) };
+        let val: &T = &this;
+        let heaped = unsafe {
+            Self::new_at::<T, _>(ptr_this, layout, this.ptr.as_ptr(), alloc, |dst| {
+                ptr::copy_nonoverlapping(sptr::from_ref(val).cast(), dst, layout.size())
+            })
+        };
+        unsafe { Pin::new_unchecked(heaped) }
+    }
+
+    #[track_caller]
+    pub(crate) unsafe fn new_copy<U>(
+        val: &U,
+        metadata_ptr: *const T,
+        alloc: A,
+    ) -> SmallBox<T, Space, A>
+    where
+        U: ?Sized,
+    {
+        match Self::try_new_copy(val, metadata_ptr, alloc) {
+            Ok(this) => this,
+            Err(err) => handle_alloc_error(err.layout()),
+        }
+    }
+
+    #[track_caller]
+    unsafe fn try_new_copy<U>(
+        val: &U,
+        metadata_ptr: *const T,
+        alloc: A,
+    ) -> Result<SmallBox<T, Space, A>, AllocError>
+    where
+        U: ?Sized,
+    {
+        let layout = Layout::for_value::<U>(val);
+        Self::try_new_init::<U, _>(layout, metadata_ptr, alloc, |dst| {
+            ptr::copy_nonoverlapping(sptr::from_ref(val).cast(), dst, layout.size())
+        })
+    }
+
+    /// Place a value of type `U` and the given layout, either inline or on heap,
+    /// and let `init` write it to the final location.
+    ///
+    /// If `init` panics, the heap memory is freed.
+    #[track_caller]
+    unsafe fn try_new_init<U, F>(
+        layout: Layout,
+        metadata_ptr: *const T,
+        alloc: A,
+        init: F,
+    ) -> Result<SmallBox<T, Space, A>, AllocError>
+    where
+        U: ?Sized,
+        F: FnOnce(*mut u8),
+    {
+        let ptr_this = Self::try_place::<U>(layout, &alloc, false)?;
+        Ok(Self::new_at::<U, _>(
+            ptr_this,
+            layout,
+            metadata_ptr,
+            alloc,
+            init,
+        ))
+    }
+
+    /// Decide where a value of type `U` and the given layout is stored, allocating
+    /// it from `alloc` if it goes on heap. Returns `inline_ptr()` for the inline space.
+    ///
+    /// With `heap`, the value goes on heap even if it fits inline or is zero-sized, so
+    /// that its address is stable. This runs the heap fallback hook, which may panic, so
+    /// callers must not have given up ownership of anything yet.
+    #[track_caller]
+    fn try_place<U>(layout: Layout, alloc: &A, heap: bool) -> Result<*mut u8, AllocError>
+    where U: ?Sized {
+        if !heap && (layout.size() == 0 || fits_inline::<Space>(layout)) {
+            #[cfg(feature = "stats")]
+            stats::record::<U, Space>(EventKind::Inline, layout);
+            return Ok(inline_ptr());
+        }
+
+        // a zero-sized value takes no memory, so there is no fallback to report
+        if layout.size() != 0 {
+            hook::heap_fallback::<U, Space>(layout);
+        }
+        let heap_ptr = alloc.allocate(layout)?.as_ptr();
+
+        #[cfg(feature = "stats")]
+        stats::record::<U, Space>(EventKind::Heap, layout);
+
+        Ok(heap_ptr)
+    }
+
+    /// Build a `SmallBox` at the place returned by `try_place`, letting `init`
+    /// write the value to it.
+    ///
+    /// If `init` panics, the heap memory is freed.
+    unsafe fn new_at<U, F>(
+        ptr_this: *mut u8,
+        layout: Layout,
+        metadata_ptr: *const T,
+        alloc: A,
+        init: F,
+    ) -> SmallBox<T, Space, A>
+    where
+        U: ?Sized,
+        F: FnOnce(*mut u8),
+    {
+        let mut space = MaybeUninit::<Space>::uninit();
+
+        let val_dst: *mut u8 = if ptr_this != inline_ptr() {
+            ptr_this
+        } else if layout.size() == 0 {
+            sptr::without_provenance_mut(layout.align())
+        } else {
+            space.as_mut_ptr().cast()
+        };
+
+        // `self.ptr` always holds the metadata, even if stack allocated
+        let ptr = NonNull::new_unchecked(sptr::with_metadata_of_mut(ptr_this, metadata_ptr));
+
+        struct DeallocOnUnwind<'a, U: ?Sized, Space, A: Allocator> {
+            ptr: *mut u8,
+            layout: Layout,
+            alloc: &'a A,
+            _phantom: PhantomData<(*const U, Space)>,
+        }
+
+        impl<'a, U: ?Sized, Space, A: Allocator> Drop for DeallocOnUnwind<'a, U, Space, A> {
+            fn drop(&mut self) {
+                if self.ptr != inline_ptr() {
+                    // the allocation was counted by `try_place`, so count the free as well
+                    #[cfg(feature = "stats")]
+                    stats::record::<U, Space>(EventKind::Free, self.layout);
+                    unsafe {
+                        self.alloc
+                            .deallocate(NonNull::new_unchecked(self.ptr), self.layout)
+                    }
+                }
+            }
+        }
+
+        let guard = DeallocOnUnwind::<U, Space, A> {
+            ptr: ptr_this,
+            layout,
+            alloc: &alloc,
+            _phantom: PhantomData,
+        };
+        init(val_dst);
+        mem::forget(guard);
+
+        SmallBox {
+            space,
+            ptr,
+            alloc,
+            #[cfg(feature = "stats")]
+            record: stats::record::<U, Space>,
+            _phantom: PhantomData,
+        }
+    }
+
+    /// Copy a value that fits the inline space into a new `SmallBox`, without
+    /// counting it again in the statistics.
+    pub(crate) unsafe fn from_inline_unchecked(val: &T, alloc: A) -> SmallBox<T, Space, A> {
+        let layout = Layout::for_value::<T>(val);
+        debug_assert!(layout.size() == 0 || fits_inline::<Space>(layout));
+        Self::new_at::<T, _>(inline_ptr(), layout, sptr::from_ref(val), alloc, |dst| {
+            ptr::copy_nonoverlapping(sptr::from_ref(val).cast(), dst, layout.size())
+        })
+    }
+
+    /// Take over a value on heap, allocated by `alloc` with the layout of the value
+    /// and recorded as a `T` in `Space`.
+    pub(crate) unsafe fn from_heap_unchecked(ptr: NonNull<T>, alloc: A) -> SmallBox<T, Space, A> {
+        SmallBox {
+            space: MaybeUninit::uninit(),
+            ptr,
+            alloc,
+            #[cfg(feature = "stats")]
+            record: stats::record::<T, Space>,
+            _phantom: PhantomData,
+        }
+    }
+
+    /// Free the heap memory, if any, without dropping the boxed value.
+    pub(crate) unsafe fn dealloc_without_drop(self) {
+        let this = ManuallyDrop::new(self);
+        let alloc = ptr::read(&this.alloc);
+        if this.is_heap() {
+            let layout = Layout::for_value::<T>(&**this);
+            #[cfg(feature = "stats")]
+            (this.record)(EventKind::Free, layout);
+            alloc.deallocate(this.ptr.cast(), layout);
+        }
+    }
+
+    /// Reinterpret the box as holding a `U`, keeping the storage as is.
+    ///
+    /// `ptr` must have the same address as `self.ptr`, only the metadata may differ.
+    unsafe fn cast_unchecked<U: ?Sized>(self, ptr: *mut U) -> SmallBox<U, Space, A> {
+        let this = ManuallyDrop::new(self);
+
+        SmallBox {
+            space: ptr::read(&this.space),
+            ptr: NonNull::new_unchecked(ptr),
+            alloc: ptr::read(&this.alloc),
+            #[cfg(feature = "stats")]
+            record: this.record,
+            _phantom: PhantomData,
+        }
+    }
+
+    /// Reinterpret the box as holding a sized `U`, keeping the storage as is.
+    pub(crate) unsafe fn cast_to_unchecked<U>(self) -> SmallBox<U, Space, A> {
+        let ptr = self.ptr.as_ptr().cast();
+        self.cast_unchecked(ptr)
+    }
+
+    #[inline]
+    unsafe fn as_ptr(&self) -> *const T {
+        if self.is_heap() {
+            self.ptr.as_ptr()
+        } else {
+            sptr::with_metadata_of(self.space.as_ptr(), self.ptr.as_ptr())
+        }
+    }
+
+    #[inline]
+    unsafe fn as_mut_ptr(&mut self) -> *mut T {
+        if self.is_heap() {
+            self.ptr.as_ptr()
+        } else {
+            sptr::with_metadata_of_mut(self.space.as_mut_ptr(), self.ptr.as_ptr())
+        }
+    }
+
+    /// Convert into a `SmallBox<U, Space>`, keeping the value in place and taking
+    /// the pointer metadata of `U` from the pointer returned by `f`.
+    ///
+    /// `smallbox_coerce!()` is the safe way to unsize a `SmallBox` on stable rust.
+    ///
+    /// # Safety
+    ///
+    /// `f` receives a pointer that carries the metadata of the value, but not necessarily
+    /// its address. The pointer it returns must have metadata valid for the value, as an
+    /// unsizing coercion like `|ptr| -> *const dyn Trait { ptr }` does.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use core::fmt::Debug;
+    ///
+    /// use smallbox::space::S1;
+    /// use smallbox::SmallBox;
+    ///
+    /// let num: SmallBox<usize, S1> = SmallBox::new(42usize);
+    /// let debug: SmallBox<dyn Debug, S1> = unsafe { num.coerce(|ptr| -> *const dyn Debug { ptr }) };
+    ///
+    /// assert_eq!(format!("{:?}", debug), "42");
+    /// ```
+    #[inline]
+    pub unsafe fn coerce<U: ?Sized>(
+        self,
+        f: impl FnOnce(*const T) -> *const U,
+    ) -> SmallBox<U, Space, A> {
+        let metadata_ptr = f(self.ptr.as_ptr());
+        self.coerce_unchecked(metadata_ptr)
+    }
+
+    #[doc(hidden)]
+    #[inline]
+    pub fn metadata_ptr(this: &Self) -> *const T {
+        this.ptr.as_ptr()
+    }
+
+    #[doc(hidden)]
+    #[inline]
+    pub unsafe fn coerce_unchecked<U: ?Sized>(
+        self,
+        metadata_ptr: *const U,
+    ) -> SmallBox<U, Space, A> {
+        let ptr = sptr::with_metadata_of_mut(self.ptr.as_ptr(), metadata_ptr);
+        self.cast_unchecked(ptr)
+    }
+
+    /// Attempt to downcast the box to a concrete type.
+    ///
+    /// This is `downcast` for trait objects of any trait with an `Any` supertrait, such
+    /// as `SmallBox<dyn Trait>` with `trait Trait: Any`. The type is looked up through the
+    /// vtable, so it is the type of the boxed value. For trait objects of traits without
+    /// an `Any` supertrait, the type is the trait object type itself, and the downcast
+    /// to a concrete type always fails.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// #[macro_use]
+    /// extern crate smallbox;
+    ///
+    /// # fn main() {
+    /// use core::any::Any;
+    ///
+    /// use smallbox::space::S1;
+    /// use smallbox::SmallBox;
+    ///
+    /// trait Named: Any {}
+    ///
+    /// impl Named for u32 {}
+    ///
+    /// let named: SmallBox<dyn Named, S1> = smallbox!(7u32);
+    /// let named = match named.downcast_dyn::<u8>() {
+    ///     Ok(_) => unreachable!(),
+    ///     Err(named) => named,
+    /// };
+    /// let num = named.downcast_dyn::<u32>().ok().unwrap();
+    ///
+    /// assert_eq!(*num, 7);
+    /// # }
+    /// ```
+    #[inline]
+    pub fn downcast_dyn<U: Any>(self) -> Result<SmallBox<U, Space, A>, Self>
+    where T: Any {
+        if Any::type_id(&*self) == TypeId::of::<U>() {
+            unsafe { Ok(self.cast_to_unchecked()) }
+        } else {
+            Err(self)
+        }
+    }
+
+    /// Attempt to downcast the box to a concrete type, using `f` to view the value
+    /// as `dyn Any`.
+    ///
+    /// Prefer the safe [`SmallBox::downcast_dyn`] for traits with an `Any` supertrait.
+    /// This is for traits that can only view the value as `dyn Any` through a method,
+    /// such as `fn as_any(&self) -> &dyn Any`, where nothing ties the result of `f` to
+    /// the boxed value.
+    ///
+    /// # Safety
+    ///
+    /// `f` must return the boxed value itself, as `self` coerced to `&dyn Any`. Returning
+    /// a field of the value, even one at the same address and of the same layout, or any
+    /// other value is undefined behavior: the box would take the value to be a `U`, and
+    /// the destructor of the whole value would never run.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// #[macro_use]
+    /// extern crate smallbox;
+    ///
+    /// # fn main() {
+    /// use core::any::Any;
+    ///
+    /// use smallbox::space::S1;
+    /// use smallbox::SmallBox;
+    ///
+    /// trait Named {
+    ///     fn as_any(&self) -> &dyn Any;
+    /// }
+    ///
+    /// impl Named for u32 {
+    ///     fn as_any(&self) -> &dyn Any {
+    ///         self
+    ///     }
+    /// }
+    ///
+    /// let named: SmallBox<dyn Named, S1> = smallbox!(7u32);
+    /// let named = match unsafe { named.downcast_with::<u8, _>(|val| val.as_any()) } {
+    ///     Ok(_) => unreachable!(),
+    ///     Err(named) => named,
+    /// };
+    /// let num = unsafe { named.downcast_with::<u32, _>(|val| val.as_any()) }
+    ///     .ok()
+    ///     .unwrap();
+    ///
+    /// assert_eq!(*num, 7);
+    /// # }
+    /// ```
+    #[inline]
+    pub unsafe fn downcast_with<U, F>(self, f: F) -> Result<SmallBox<U, Space, A>, Self>
+    where
+        U: Any,
+        F: FnOnce(&T) -> &dyn Any,
+    {
+        let any = f(&*self);
+        if !any.is::<U>() {
+            return Err(self);
+        }
+        debug_assert!(same_object(&*self, any));
+        Ok(self.cast_to_unchecked())
+    }
+
+    /// Consumes the SmallBox and returns ownership of the boxed value
+    ///
+    /// # Examples
+    /// ```
+    /// # #[cfg(feature = "alloc")]
+    /// # {
+    /// use smallbox::space::S1;
+    /// use smallbox::SmallBox;
+    ///
+    /// let stacked: SmallBox<_, S1> = SmallBox::new([21usize]);
+    /// let val = stacked.into_inner();
+    /// assert_eq!(val[0], 21);
+    ///
+    /// let boxed: SmallBox<_, S1> = SmallBox::new(vec![21, 56, 420]);
+    /// let val = boxed.into_inner();
+    /// assert_eq!(val[1], 56);
+    /// # }
+    /// ```
+    #[inline]
+    pub fn into_inner(self) -> T
+    where T: Sized {
+        let ret_val: T = unsafe { self.as_ptr().read() };
+
+        // Just drops the heap without dropping the boxed value
+        unsafe { self.dealloc_without_drop() };
+
+        ret_val
+    }
+}
+
+impl<T, Space> SmallBox<MaybeUninit<T>, Space> {
+    /// Create a new box with uninitialized contents, on stack or on heap depending on its size.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use smallbox::space::S2;
+    /// use smallbox::SmallBox;
+    ///
+    /// let mut five = SmallBox::<_, S2>::new_uninit();
+    /// five.write(5u32);
+    /// let five = unsafe { five.assume_init() };
+    ///
+    /// assert_eq!(*five, 5);
+    /// ```
+    #[inline]
+    #[track_caller]
+    pub fn new_uninit() -> SmallBox<MaybeUninit<T>, Space> {
+        Self::new_uninit_in(Global)
+    }
+
+    /// Create a new box with contents filled with `0` bytes, on stack or on heap
+    /// depending on its size.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use smallbox::space::S2;
+    /// use smallbox::SmallBox;
+    ///
+    /// let zero = SmallBox::<_, S2>::new_zeroed();
+    /// let zero: SmallBox<u32, S2> = unsafe { zero.assume_init() };
+    ///
+    /// assert_eq!(*zero, 0);
+    /// ```
+    #[inline]
+    #[track_caller]
+    pub fn new_zeroed() -> SmallBox<MaybeUninit<T>, Space> {
+        Self::new_zeroed_in(Global)
+    }
+}
+
+impl<T, Space, A: Allocator> SmallBox<MaybeUninit<T>, Space, A> {
+    /// Create a new box with uninitialized contents, using the given allocator
+    /// for the heap fallback.
+    #[inline]
+    #[track_caller]
+    pub fn new_uninit_in(alloc: A) -> SmallBox<MaybeUninit<T>, Space, A> {
+        #[allow(clippy::let_unit_value)]
+        let () = AssertCanStore::<T, Space, A>::ASSERT;
+        let ptr = NonNull::<MaybeUninit<T>>::dangling().as_ptr();
+        let layout = Layout::new::<T>();
+        match unsafe { Self::try_new_init::<T, _>(layout, ptr, alloc, |_| {}) } {
+            Ok(this) => this,
+            Err(err) => handle_alloc_error(err.layout()),
+        }
+    }
+
+    /// Create a new box with contents filled with `0` bytes, using the given
+    /// allocator for the heap fallback.
+    #[inline]
+    #[track_caller]
+    pub fn new_zeroed_in(alloc: A) -> SmallBox<MaybeUninit<T>, Space, A> {
+        #[allow(clippy::let_unit_value)]
+        let () = AssertCanStore::<T, Space, A>::ASSERT;
+        let ptr = NonNull::<MaybeUninit<T>>::dangling().as_ptr();
+        let layout = Layout::new::<T>();
+        let init = |dst: *mut u8| unsafe { ptr::write_bytes(dst, 0, layout.size()) };
+        match unsafe { Self::try_new_init::<T, _>(layout, ptr, alloc, init) } {
+            Ok(this) => this,
+            Err(err) => handle_alloc_error(err.layout()),
+        }
+    }
+
+    /// Convert to `SmallBox<T, Space>`.
+    ///
+    /// # Safety
+    ///
+    /// The value must be fully initialized, see [`MaybeUninit::assume_init`].
+    #[inline]
+    pub unsafe fn assume_init(self) -> SmallBox<T, Space, A> {
+        let ptr = self.ptr.as_ptr().cast::<T>();
+        self.cast_unchecked(ptr)
+    }
+}
+
+impl<T, Space> SmallBox<[MaybeUninit<T>], Space> {
+    /// Create a new boxed slice with uninitialized contents, on stack or on heap
+    /// depending on its size.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the size of the slice overflows `isize::MAX`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # #[cfg(feature = "alloc")]
+    /// # {
+    /// use smallbox::space::S4;
+    /// use smallbox::SmallBox;
+    ///
+    /// let mut values = SmallBox::<[_], S4>::new_uninit_slice(3);
+    /// for (i, value) in values.iter_mut().enumerate() {
+    ///     value.write(i);
+    /// }
+    /// let values = unsafe { values.assume_init() };
+    ///
+    /// assert_eq!(*values, [0, 1, 2]);
+    /// assert!(!values.is_heap());
+    /// # }
+    /// ```
+    #[cfg(feature = "alloc")]
+    #[inline]
+    #[track_caller]
+    pub fn new_uninit_slice(len: usize) -> SmallBox<[MaybeUninit<T>], Space> {
+        Self::new_uninit_slice_in(len, Global)
+    }
+
+    /// Create a new boxed slice with contents filled with `0` bytes, on stack
+    /// or on heap depending on its size.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the size of the slice overflows `isize::MAX`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # #[cfg(feature = "alloc")]
+    /// # {
+    /// use smallbox::space::S4;
+    /// use smallbox::SmallBox;
+    ///
+    /// let values = SmallBox::<[_], S4>::new_zeroed_slice(3);
+    /// let values: SmallBox<[u32], S4> = unsafe { values.assume_init() };
+    ///
+    /// assert_eq!(*values, [0, 0, 0]);
+    /// # }
+    /// ```
+    #[cfg(feature = "alloc")]
+    #[inline]
+    #[track_caller]
+    pub fn new_zeroed_slice(len: usize) -> SmallBox<[MaybeUninit<T>], Space> {
+        Self::new_zeroed_slice_in(len, Global)
+    }
+}
+
+impl<T, Space, A: Allocator> SmallBox<[MaybeUninit<T>], Space, A> {
+    /// Create a new boxed slice with uninitialized contents, using the given
+    /// allocator for the heap fallback.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the size of the slice overflows `isize::MAX`.
+    #[inline]
+    #[track_caller]
+    pub fn new_uninit_slice_in(len: usize, alloc: A) -> SmallBox<[MaybeUninit<T>], Space, A> {
+        #[allow(clippy::let_unit_value)]
+        let () = AssertCanAllocate::<A>::ASSERT;
+        let ptr =
+            ptr::slice_from_raw_parts_mut(NonNull::<MaybeUninit<T>>::dangling().as_ptr(), len);
+        let layout = Layout::array::<T>(len).expect("capacity overflow");
+        match unsafe { Self::try_new_init::<[T], _>(layout, ptr, alloc, |_| {}) } {
+            Ok(this) => this,
+            Err(err) => handle_alloc_error(err.layout()),
+        }
+    }
+
+    /// Create a new boxed slice with contents filled with `0` bytes, using the
+    /// given allocator for the heap fallback.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the size of the slice overflows `isize::MAX`.
+    #[inline]
+    #[track_caller]
+    pub fn new_zeroed_slice_in(len: usize, alloc: A) -> SmallBox<[MaybeUninit<T>], Space, A> {
+        #[allow(clippy::let_unit_value)]
+        let () = AssertCanAllocate::<A>::ASSERT;
+        let ptr =
+            ptr::slice_from_raw_parts_mut(NonNull::<MaybeUninit<T>>::dangling().as_ptr(), len);
+        let layout = Layout::array::<T>(len).expect("capacity overflow");
+        let init = |dst: *mut u8| unsafe { ptr::write_bytes(dst, 0, layout.size()) };
+        match unsafe { Self::try_new_init::<[T], _>(layout, ptr, alloc, init) } {
+            Ok(this) => this,
+            Err(err) => handle_alloc_error(err.layout()),
+        }
+    }
+
+    /// Convert to `SmallBox<[T], Space>`.
+    ///
+    /// # Safety
+    ///
+    /// All elements must be fully initialized, see [`MaybeUninit::assume_init`].
+    #[inline]
+    pub unsafe fn assume_init(self) -> SmallBox<[T], Space, A> {
+        let ptr = ptr::slice_from_raw_parts_mut(self.ptr.as_ptr().cast::<T>(), self.len());
+        self.cast_unchecked(ptr)
+    }
+}
+
+macro_rules! impl_any {
+    ($($bound:ident),*) => {
+        impl<Space, A: Allocator> SmallBox<dyn Any $(+ $bound)*, Space, A> {
+            /// Attempt to downcast the box to a concrete type.
+            ///
+            /// The value is kept in place, neither the inline space nor the heap memory is copied.
+            ///
+            /// # Examples
+            ///
+            /// ```
+            /// #[macro_use]
+            /// extern crate smallbox;
+            ///
+            /// # fn main() {
+            /// use core::any::Any;
+            ///
+            /// use smallbox::space::*;
+            /// use smallbox::SmallBox;
+            ///
+            /// fn print_if_string(value: SmallBox<dyn Any, S1>) {
+            ///     if let Ok(string) = value.downcast::<String>() {
+            ///         println!("String ({}): {}", string.len(), string);
+            ///     }
+            /// }
+            ///
+            /// fn main() {
+            ///     let my_string = "Hello World".to_string();
+            ///     print_if_string(smallbox!(my_string));
+            ///     print_if_string(smallbox!(0i8));
+            /// }
+            /// # }
+            /// ```
+            #[inline]
+            pub fn downcast<T: Any>(self) -> Result<SmallBox<T, Space, A>, Self> {
+                if self.is::<T>() {
+                    unsafe { Ok(self.downcast_unchecked()) }
+                } else {
+                    Err(self)
+                }
+            }
+
+            /// Downcast the box to a concrete type without checking the type.
+            ///
+            /// # Safety
+            ///
+            /// The boxed value must be a `T`, calling this method with the incorrect
+            /// type is undefined behavior.
+            ///
+            /// # Examples
+            ///
+            /// ```
+            /// #[macro_use]
+            /// extern crate smallbox;
+            ///
+            /// # fn main() {
+            /// use core::any::Any;
+            ///
+            /// use smallbox::space::*;
+            /// use smallbox::SmallBox;
+            ///
+            /// let num: SmallBox<dyn Any + Send + Sync, S1> = smallbox!(1usize);
+            ///
+            /// unsafe {
+            ///     assert_eq!(*num.downcast_unchecked::<usize>(), 1);
+            /// }
+            /// # }
+            /// ```
+            #[inline]
+            pub unsafe fn downcast_unchecked<T: Any>(self) -> SmallBox<T, Space, A> {
+                debug_assert!(self.is::<T>());
+                self.cast_to_unchecked()
+            }
+        }
+    };
+}
+
+impl_any!();
+impl_any!(Send);
+impl_any!(Send, Sync);
+
+impl<T: ?Sized, Space, A: Allocator> ops::Deref for SmallBox<T, Space, A> {
     type Target = T;
 
-    fn deref(&self) -> &T {
-        unsafe { &*self.as_ptr() }
+    fn deref(&self) -> &T {
+        unsafe { &*self.as_ptr() }
+    }
+}
+
+impl<T: ?Sized, Space, A: Allocator> ops::DerefMut for SmallBox<T, Space, A> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.as_mut_ptr() }
+    }
+}
+
+impl<T: ?Sized, Space, A: Allocator> AsRef<T> for SmallBox<T, Space, A> {
+    fn as_ref(&self) -> &T {
+        self
+    }
+}
+
+impl<T: ?Sized, Space, A: Allocator> AsMut<T> for SmallBox<T, Space, A> {
+    fn as_mut(&mut self) -> &mut T {
+        self
+    }
+}
+
+impl<T: ?Sized, Space, A: Allocator> Borrow<T> for SmallBox<T, Space, A> {
+    fn borrow(&self) -> &T {
+        self
+    }
+}
+
+impl<T: ?Sized, Space, A: Allocator> BorrowMut<T> for SmallBox<T, Space, A> {
+    fn borrow_mut(&mut self) -> &mut T {
+        self
+    }
+}
+
+impl<T: ?Sized, Space, A: Allocator> ops::Drop for SmallBox<T, Space, A> {
+    fn drop(&mut self) {
+        unsafe {
+            let layout = Layout::for_value::<T>(&*self);
+            ptr::drop_in_place::<T>(&mut **self);
+            if self.is_heap() {
+                #[cfg(feature = "stats")]
+                (self.record)(EventKind::Free, layout);
+                self.alloc.deallocate(self.ptr.cast(), layout);
+            }
+        }
+    }
+}
+
+impl<T: ?Sized + SmallClone, Space, A: Allocator + Clone> SmallBox<T, Space, A> {
+    /// Clone the boxed value, returning an error if the clone has to be
+    /// stored on heap and the allocation fails.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # #[cfg(feature = "alloc")]
+    /// # {
+    /// use smallbox::space::S1;
+    /// use smallbox::SmallBox;
+    ///
+    /// let heaped: SmallBox<_, S1> = SmallBox::new([1usize, 2]);
+    /// let cloned = heaped.try_clone().unwrap();
+    /// assert_eq!(*cloned, [1, 2]);
+    /// # }
+    /// ```
+    #[track_caller]
+    pub fn try_clone(&self) -> Result<Self, AllocError> {
+        Self::try_clone_in(self, self.alloc.clone())
+    }
+
+    /// Clone `val`, on stack or on heap depending on its layout.
+    #[track_caller]
+    fn try_clone_in(val: &T, alloc: A) -> Result<Self, AllocError> {
+        let layout = Layout::for_value::<T>(val);
+        unsafe {
+            Self::try_new_init::<T, _>(layout, sptr::from_ref(val), alloc, |dst| {
+                clone::clone_to(val, dst)
+            })
+        }
+    }
+}
+
+impl<T: ?Sized + SmallClone, Space, A: Allocator + Clone> Clone for SmallBox<T, Space, A> {
+    #[track_caller]
+    fn clone(&self) -> Self {
+        match self.try_clone() {
+            Ok(this) => this,
+            Err(err) => handle_alloc_error(err.layout()),
+        }
+    }
+}
+
+/// The address of `SmallBox::ptr` for values stored inline.
+///
+/// No allocation starts at the last address, so it can't be mistaken for a heap
+/// pointer, and unlike null it leaves a niche for `Option<SmallBox>`.
+#[inline]
+pub(crate) fn inline_ptr() -> *mut u8 {
+    sptr::without_provenance_mut(usize::MAX)
+}
+
+/// Returns whether `view` covers exactly the memory of `val`. This can't tell the value
+/// apart from a field of the same layout at its start, so it is only a sanity check.
+#[inline]
+fn same_object<T: ?Sized, U: ?Sized>(val: &T, view: &U) -> bool {
+    sptr::from_ref(val).cast::<u8>() == sptr::from_ref(view).cast::<u8>()
+        && mem::size_of_val(val) == mem::size_of_val(view)
+        && mem::align_of_val(val) == mem::align_of_val(view)
+}
+
+/// Returns whether a value with the given layout can be stored in `Space`.
+#[inline]
+fn fits_inline<Space>(layout: Layout) -> bool {
+    layout.size() <= mem::size_of::<Space>() && layout.align() <= mem::align_of::<Space>()
+}
+
+/// Evaluates to a compile-time error if a `U` doesn't fit in `Space` and `A` can't allocate.
+struct AssertCanStore<U, Space, A>(PhantomData<(U, Space, A)>);
+
+impl<U, Space, A: Allocator> AssertCanStore<U, Space, A> {
+    const ASSERT: () = assert!(
+        A::CAN_ALLOCATE || mem::size_of::<U>() == 0 || stackbox::fits::<U, Space>(),
+        "the value does not fit in the size or alignment of the space, and the allocator can't allocate"
+    );
+}
+
+/// Evaluates to a compile-time error if a value stored in `Space` or by `A` may not fit in
+/// `ToSpace` and `B` can't allocate.
+struct AssertCanResize<Space, ToSpace, A, B>(PhantomData<(Space, ToSpace, A, B)>);
+
+impl<Space, ToSpace, A: Allocator, B: Allocator> AssertCanResize<Space, ToSpace, A, B> {
+    const ASSERT: () = assert!(
+        B::CAN_ALLOCATE || (!A::CAN_ALLOCATE && stackbox::fits::<Space, ToSpace>()),
+        "the space may not fit in the new space, and the allocator can't allocate"
+    );
+}
+
+/// Evaluates to a compile-time error if `A` can't allocate.
+struct AssertCanAllocate<A>(PhantomData<A>);
+
+impl<A: Allocator> AssertCanAllocate<A> {
+    const ASSERT: () = assert!(A::CAN_ALLOCATE, "the allocator can't allocate");
+}
+
+impl<T: Clone, Space> From<&[T]> for SmallBox<[T], Space> {
+    /// Clone the elements of the slice, on stack or on heap depending on its length.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use smallbox::space::S4;
+    /// use smallbox::SmallBox;
+    ///
+    /// let values: SmallBox<[usize], S4> = SmallBox::from(&[1, 2, 3][..]);
+    ///
+    /// assert_eq!(*values, [1, 2, 3]);
+    /// assert!(!values.is_heap());
+    /// ```
+    #[track_caller]
+    fn from(slice: &[T]) -> Self {
+        match SmallBox::try_clone_in(slice, Global) {
+            Ok(this) => this,
+            Err(err) => handle_alloc_error(err.layout()),
+        }
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl<T, Space> From<Vec<T>> for SmallBox<[T], Space> {
+    /// Move the elements of the vector inline if they fit in the space,
+    /// or adopt the heap allocation of the vector otherwise.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use smallbox::space::S4;
+    /// use smallbox::SmallBox;
+    ///
+    /// let small: SmallBox<[usize], S4> = SmallBox::from(vec![1, 2]);
+    /// let large: SmallBox<[usize], S4> = SmallBox::from(vec![0; 8]);
+    ///
+    /// assert!(!small.is_heap());
+    /// assert!(large.is_heap());
+    /// ```
+    #[track_caller]
+    fn from(mut vec: Vec<T>) -> Self {
+        let layout = Layout::for_value::<[T]>(&vec);
+        if layout.size() != 0 && !fits_inline::<Space>(layout) {
+            hook::heap_fallback::<[T], Space>(layout);
+            return SmallBox::from_box(vec.into_boxed_slice());
+        }
+
+        let mut uninit = SmallBox::<[MaybeUninit<T>], Space>::new_uninit_slice(vec.len());
+        unsafe {
+            ptr::copy_nonoverlapping(vec.as_ptr(), (*uninit).as_mut_ptr().cast::<T>(), vec.len());
+            vec.set_len(0);
+            uninit.assume_init()
+        }
+    }
+}
+
+impl<T, Space> FromIterator<T> for SmallBox<[T], Space> {
+    /// Collect the elements in the space, and only move them to the heap once
+    /// they don't fit anymore.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use smallbox::space::S4;
+    /// use smallbox::SmallBox;
+    ///
+    /// let values: SmallBox<[usize], S4> = (0..4).collect();
+    ///
+    /// assert_eq!(*values, [0, 1, 2, 3]);
+    /// assert!(!values.is_heap());
+    /// ```
+    #[track_caller]
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        let mut iter = iter.into_iter();
+
+        let mut space = MaybeUninit::<Space>::uninit();
+        let (buf, cap) = if mem::size_of::<T>() == 0 {
+            (NonNull::<T>::dangling().as_ptr(), usize::MAX)
+        } else if mem::align_of::<T>() > mem::align_of::<Space>() {
+            (NonNull::<T>::dangling().as_ptr(), 0)
+        } else {
+            let cap = mem::size_of::<Space>() / mem::size_of::<T>();
+            (space.as_mut_ptr().cast::<T>(), cap)
+        };
+
+        let mut guard = PartialSlice { ptr: buf, len: 0 };
+        let mut next = iter.next();
+        while let Some(val) = next.take() {
+            if guard.len == cap {
+                next = Some(val);
+                break;
+            }
+            unsafe { guard.ptr.add(guard.len).write(val) };
+            guard.len += 1;
+            next = iter.next();
+        }
+
+        match next {
+            None => {
+                let len = guard.len;
+                mem::forget(guard);
+
+                // the elements were collected in `space`, so they fit inline
+                let layout = Layout::array::<T>(len).expect("capacity overflow");
+                let metadata_ptr = ptr::slice_from_raw_parts(buf, len);
+                let init = |dst: *mut u8| unsafe { ptr::copy_nonoverlapping(buf, dst.cast(), len) };
+                match unsafe { Self::try_new_init::<[T], _>(layout, metadata_ptr, Global, init) } {
+                    Ok(this) => this,
+                    Err(err) => handle_alloc_error(err.layout()),
+                }
+            }
+            #[cfg(feature = "alloc")]
+            Some(next) => {
+                let (lower, _) = iter.size_hint();
+                let mut vec = Vec::with_capacity(cap.saturating_add(lower).saturating_add(1));
+                unsafe {
+                    ptr::copy_nonoverlapping(buf, vec.as_mut_ptr(), cap);
+                    vec.set_len(cap);
+                }
+                mem::forget(guard);
+                vec.push(next);
+                vec.extend(iter);
+
+                // the conversion from `Vec` invokes the heap fallback hook
+                SmallBox::from(vec)
+            }
+            #[cfg(not(feature = "alloc"))]
+            Some(_) => {
+                let layout = Layout::array::<T>(cap + 1).expect("capacity overflow");
+                handle_alloc_error(layout)
+            }
+        }
+    }
+}
+
+impl<Space, A: Allocator> SmallBox<str, Space, A> {
+    /// Reinterpret boxed bytes as a string slice.
+    ///
+    /// # Safety
+    ///
+    /// The bytes must be valid UTF-8.
+    #[allow(clippy::as_conversions)]
+    unsafe fn from_utf8_unchecked(bytes: SmallBox<[u8], Space, A>) -> SmallBox<str, Space, A> {
+        let ptr = bytes.ptr.as_ptr() as *mut str;
+        bytes.cast_unchecked(ptr)
+    }
+}
+
+impl<Space> From<&str> for SmallBox<str, Space> {
+    /// Copy the string slice, on stack or on heap depending on its length.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use smallbox::space::S4;
+    /// use smallbox::SmallBox;
+    ///
+    /// let name: SmallBox<str, S4> = SmallBox::from("smallbox");
+    ///
+    /// assert_eq!(&*name, "smallbox");
+    /// assert!(!name.is_heap());
+    /// ```
+    #[track_caller]
+    fn from(s: &str) -> Self {
+        match SmallBox::try_clone_in(s, Global) {
+            Ok(this) => this,
+            Err(err) => handle_alloc_error(err.layout()),
+        }
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl<Space> From<String> for SmallBox<str, Space> {
+    /// Move the string inline if it fits in the space, or adopt the heap
+    /// allocation of the string otherwise.
+    #[track_caller]
+    fn from(s: String) -> Self {
+        unsafe { SmallBox::from_utf8_unchecked(SmallBox::from(s.into_bytes())) }
+    }
+}
+
+impl<Space> From<char> for SmallBox<str, Space> {
+    /// Encode the character as UTF-8.
+    #[track_caller]
+    fn from(c: char) -> Self {
+        let mut buf = [0; 4];
+        SmallBox::from(&*c.encode_utf8(&mut buf))
+    }
+}
+
+impl<T: ?Sized + fmt::Display, Space, A: Allocator> fmt::Display for SmallBox<T, Space, A> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(&**self, f)
+    }
+}
+
+impl<T: ?Sized + fmt::Debug, Space, A: Allocator> fmt::Debug for SmallBox<T, Space, A> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+impl<T: ?Sized, Space, A: Allocator> fmt::Pointer for SmallBox<T, Space, A> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // It's not possible to extract the inner Unique directly from the Box,
+        // instead we cast it to a *const which aliases the Unique
+        let ptr: *const T = &**self;
+        fmt::Pointer::fmt(&ptr, f)
+    }
+}
+
+impl<T: ?Sized + PartialEq, Space, A: Allocator> PartialEq for SmallBox<T, Space, A> {
+    fn eq(&self, other: &SmallBox<T, Space, A>) -> bool {
+        PartialEq::eq(&**self, &**other)
+    }
+}
+
+impl<T: ?Sized + PartialOrd, Space, A: Allocator> PartialOrd for SmallBox<T, Space, A> {
+    fn partial_cmp(&self, other: &SmallBox<T, Space, A>) -> Option<Ordering> {
+        PartialOrd::partial_cmp(&**self, &**other)
+    }
+    fn lt(&self, other: &SmallBox<T, Space, A>) -> bool {
+        PartialOrd::lt(&**self, &**other)
+    }
+    fn le(&self, other: &SmallBox<T, Space, A>) -> bool {
+        PartialOrd::le(&**self, &**other)
+    }
+    fn ge(&self, other: &SmallBox<T, Space, A>) -> bool {
+        PartialOrd::ge(&**self, &**other)
+    }
+    fn gt(&self, other: &SmallBox<T, Space, A>) -> bool {
+        PartialOrd::gt(&**self, &**other)
+    }
+}
+
+impl<T: ?Sized + Ord, Space, A: Allocator> Ord for SmallBox<T, Space, A> {
+    fn cmp(&self, other: &SmallBox<T, Space, A>) -> Ordering {
+        Ord::cmp(&**self, &**other)
+    }
+}
+
+impl<T: ?Sized + Eq, Space, A: Allocator> Eq for SmallBox<T, Space, A> {}
+
+impl<T: ?Sized + Hash, Space, A: Allocator> Hash for SmallBox<T, Space, A> {
+    fn hash<H: hash::Hasher>(&self, state: &mut H) {
+        (**self).hash(state);
+    }
+}
+
+impl<I: ?Sized + Iterator, Space, A: Allocator> Iterator for SmallBox<I, Space, A> {
+    type Item = I::Item;
+
+    fn next(&mut self) -> Option<I::Item> {
+        (**self).next()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (**self).size_hint()
+    }
+
+    fn nth(&mut self, n: usize) -> Option<I::Item> {
+        (**self).nth(n)
+    }
+}
+
+impl<I: ?Sized + DoubleEndedIterator, Space, A: Allocator> DoubleEndedIterator
+    for SmallBox<I, Space, A>
+{
+    fn next_back(&mut self) -> Option<I::Item> {
+        (**self).next_back()
+    }
+
+    fn nth_back(&mut self, n: usize) -> Option<I::Item> {
+        (**self).nth_back(n)
+    }
+}
+
+impl<I: ?Sized + ExactSizeIterator, Space, A: Allocator> ExactSizeIterator
+    for SmallBox<I, Space, A>
+{
+    fn len(&self) -> usize {
+        (**self).len()
+    }
+}
+
+impl<I: ?Sized + FusedIterator, Space, A: Allocator> FusedIterator for SmallBox<I, Space, A> {}
+
+impl<F: ?Sized + Future + Unpin, Space, A: Allocator> Future for SmallBox<F, Space, A> {
+    type Output = F::Output;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<F::Output> {
+        Pin::new(&mut **self).poll(cx)
+    }
+}
+
+impl<T, Space> From<T> for SmallBox<T, Space> {
+    /// Box value on stack or on heap depending on its size, like [`SmallBox::new`].
+    #[track_caller]
+    fn from(val: T) -> Self {
+        SmallBox::new(val)
+    }
+}
+
+impl<T: Default, Space, A: Allocator + Default> Default for SmallBox<T, Space, A> {
+    #[track_caller]
+    fn default() -> Self {
+        SmallBox::new_in(T::default(), A::default())
+    }
+}
+
+impl<T, Space, A: Allocator + Default> Default for SmallBox<[T], Space, A> {
+    fn default() -> Self {
+        // an empty slice is zero-sized and never allocates
+        let metadata_ptr = ptr::slice_from_raw_parts(NonNull::<T>::dangling().as_ptr(), 0);
+        match unsafe {
+            Self::try_new_init::<[T], _>(
+                Layout::new::<[T; 0]>(),
+                metadata_ptr,
+                A::default(),
+                |_| {},
+            )
+        } {
+            Ok(this) => this,
+            Err(err) => handle_alloc_error(err.layout()),
+        }
+    }
+}
+
+impl<Space, A: Allocator + Default> Default for SmallBox<str, Space, A> {
+    fn default() -> Self {
+        unsafe { SmallBox::from_utf8_unchecked(SmallBox::default()) }
+    }
+}
+
+/// Unlike `Box<T>`, which is `Unpin` for every `T`, a `SmallBox<T>` is only `Unpin` if `T` is.
+///
+/// A value stored inline moves whenever the `SmallBox` moves. If the box were always
+/// `Unpin`, a `Pin<SmallBox<T>>` could be moved freely and take a pinned inline value
+/// with it, so the box is only as `Unpin` as its value. This is a deliberate difference
+/// from `Box`. `SmallBox::pin` and `SmallBox::into_pin` still pin any value, on heap.
+impl<T: ?Sized + Unpin, Space, A: Allocator> Unpin for SmallBox<T, Space, A> {}
+
+impl<T: ?Sized + UnwindSafe, Space, A: Allocator + UnwindSafe> UnwindSafe
+    for SmallBox<T, Space, A>
+{
+}
+impl<T: ?Sized + RefUnwindSafe, Space, A: Allocator + RefUnwindSafe> RefUnwindSafe
+    for SmallBox<T, Space, A>
+{
+}
+
+unsafe impl<T: ?Sized + Send, Space, A: Allocator + Send> Send for SmallBox<T, Space, A> {}
+unsafe impl<T: ?Sized + Sync, Space, A: Allocator + Sync> Sync for SmallBox<T, Space, A> {}
+
+#[cfg(test)]
+mod tests {
+    use core::alloc::Layout;
+    use core::any::Any;
+    use core::cell::Cell;
+    use core::future::Future;
+    use core::mem::MaybeUninit;
+    use core::mem::{self};
+    use core::pin::Pin;
+    use core::ptr;
+    use core::ptr::addr_of;
+    use core::ptr::NonNull;
+    use core::task::Context;
+    use core::task::Poll;
+    use core::task::RawWaker;
+    use core::task::RawWakerVTable;
+    use core::task::Waker;
+
+    #[cfg(feature = "alloc")]
+    use ::alloc::boxed::Box;
+    #[cfg(feature = "alloc")]
+    use ::alloc::format;
+    #[cfg(feature = "alloc")]
+    use ::alloc::string::String;
+    #[cfg(feature = "alloc")]
+    use ::alloc::string::ToString;
+    #[cfg(feature = "alloc")]
+    use ::alloc::vec;
+
+    use super::SmallBox;
+    use crate::space::*;
+    use crate::AllocError;
+    use crate::Allocator;
+    use crate::Global;
+    use crate::SmallClone;
+
+    #[test]
+    fn test_basic() {
+        let stacked: SmallBox<usize, S1> = SmallBox::new(1234usize);
+        assert!(*stacked == 1234);
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn test_basic_heap() {
+        let heaped: SmallBox<(usize, usize), S1> = SmallBox::new((0, 1));
+        assert!(*heaped == (0, 1));
+    }
+
+    #[test]
+    fn test_new_unchecked() {
+        let val = [0usize, 1];
+        let ptr = addr_of!(val);
+
+        unsafe {
+            let stacked: SmallBox<[usize], S2> = SmallBox::new_unchecked(val, ptr);
+            assert!(*stacked == [0, 1]);
+            assert!(!stacked.is_heap());
+        }
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn test_new_unchecked_heap() {
+        let val = [0usize, 1, 2];
+        let ptr = addr_of!(val);
+
+        unsafe {
+            let heaped: SmallBox<dyn Any, S2> = SmallBox::new_unchecked(val, ptr);
+            assert!(heaped.is_heap());
+
+            if let Some(array) = heaped.downcast_ref::<[usize; 3]>() {
+                assert_eq!(*array, [0, 1, 2]);
+            } else {
+                unreachable!();
+            }
+        }
+    }
+
+    #[test]
+    #[deny(unsafe_code)]
+    fn test_macro() {
+        let stacked: SmallBox<dyn Any, S1> = smallbox!(1234usize);
+        if let Some(num) = stacked.downcast_ref::<usize>() {
+            assert_eq!(*num, 1234);
+        } else {
+            unreachable!();
+        }
+
+        let is_even: SmallBox<dyn Fn(u8) -> bool, S1> = smallbox!(|num: u8| num % 2 == 0);
+        assert!(!is_even(5));
+        assert!(is_even(6));
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    #[deny(unsafe_code)]
+    fn test_macro_heap() {
+        let heaped: SmallBox<dyn Any, S1> = smallbox!([0usize, 1]);
+        if let Some(array) = heaped.downcast_ref::<[usize; 2]>() {
+            assert_eq!(*array, [0, 1]);
+        } else {
+            unreachable!();
+        }
+    }
+
+    #[cfg(all(feature = "alloc", feature = "coerce"))]
+    #[test]
+    fn test_coerce() {
+        let stacked: SmallBox<dyn Any, S1> = SmallBox::new(1234usize);
+        if let Some(num) = stacked.downcast_ref::<usize>() {
+            assert_eq!(*num, 1234);
+        } else {
+            unreachable!();
+        }
+
+        let heaped: SmallBox<dyn Any, S1> = SmallBox::new([0usize, 1]);
+        if let Some(array) = heaped.downcast_ref::<[usize; 2]>() {
+            assert_eq!(*array, [0, 1]);
+        } else {
+            unreachable!();
+        }
+    }
+
+    #[allow(dead_code)]
+    struct DropFlag<'a>(&'a Cell<bool>, u8);
+
+    impl<'a> Drop for DropFlag<'a> {
+        fn drop(&mut self) {
+            self.0.set(true);
+        }
+    }
+
+    #[test]
+    fn test_drop() {
+        let flag = Cell::new(false);
+        let stacked: SmallBox<_, S2> = SmallBox::new(DropFlag(&flag, 0));
+        assert!(!stacked.is_heap());
+        assert!(!flag.get());
+        drop(stacked);
+        assert!(flag.get());
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn test_drop_heap() {
+        let flag = Cell::new(false);
+        let heaped: SmallBox<_, S1> = SmallBox::new(DropFlag(&flag, 0));
+        assert!(heaped.is_heap());
+        assert!(!flag.get());
+        drop(heaped);
+        assert!(flag.get());
+    }
+
+    #[test]
+    fn test_dont_drop_space() {
+        #[allow(dead_code)]
+        struct NoDrop(S1);
+        impl Drop for NoDrop {
+            fn drop(&mut self) {
+                unreachable!();
+            }
+        }
+
+        drop(SmallBox::<_, NoDrop>::new([true]));
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn test_oversize() {
+        let fit = SmallBox::<_, S1>::new([1usize]);
+        let oversize = SmallBox::<_, S1>::new([1usize, 2]);
+        assert!(!fit.is_heap());
+        assert!(oversize.is_heap());
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn test_resize() {
+        let m = SmallBox::<_, S4>::new([1usize, 2]);
+        let l = m.resize::<S8>();
+        assert!(!l.is_heap());
+        let m = l.resize::<S4>();
+        assert!(!m.is_heap());
+        let s = m.resize::<S2>();
+        assert!(!s.is_heap());
+        let xs = s.resize::<S1>();
+        assert!(xs.is_heap());
+        let m = xs.resize::<S4>();
+        assert!(m.is_heap());
+        assert_eq!(*m, [1usize, 2]);
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn test_try_new() {
+        let stacked: SmallBox<usize, S1> = SmallBox::try_new(1234usize).unwrap();
+        assert!(!stacked.is_heap());
+        assert_eq!(*stacked, 1234);
+
+        let heaped: SmallBox<(usize, usize), S1> = SmallBox::try_new((0, 1)).unwrap();
+        assert!(heaped.is_heap());
+        assert_eq!(*heaped, (0, 1));
+
+        let unsized_box: Result<SmallBox<[usize], S1>, AllocError> = try_smallbox!([0usize, 1]);
+        let unsized_box = unsized_box.unwrap();
+        assert!(unsized_box.is_heap());
+        assert_eq!(*unsized_box, [0, 1]);
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn test_try_resize() {
+        let m = SmallBox::<_, S4>::new([1usize, 2]);
+        let s = m.try_resize::<S2>().unwrap();
+        assert!(!s.is_heap());
+        let xs = s.try_resize::<S1>().unwrap();
+        assert!(xs.is_heap());
+        let m = xs.try_resize::<S4>().unwrap();
+        assert!(m.is_heap());
+        assert_eq!(*m, [1usize, 2]);
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn test_try_clone() {
+        let stacked: SmallBox<[usize; 2], S2> = smallbox!([1usize, 2]);
+        assert_eq!(stacked, stacked.try_clone().unwrap());
+
+        let heaped: SmallBox<[usize; 2], S1> = smallbox!([1usize, 2]);
+        let cloned = heaped.try_clone().unwrap();
+        assert!(cloned.is_heap());
+        assert_eq!(heaped, cloned);
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn test_alloc_error() {
+        let layout = Layout::new::<[usize; 4]>();
+        let err = AllocError::new(layout);
+        assert_eq!(err.layout(), layout);
+        assert_eq!(
+            err.to_string(),
+            format!("memory allocation of {} bytes failed", layout.size())
+        );
+    }
+
+    #[cfg(feature = "alloc")]
+    struct CountingAlloc {
+        allocated: Cell<usize>,
+        deallocated: Cell<usize>,
+    }
+
+    #[cfg(feature = "alloc")]
+    impl CountingAlloc {
+        fn new() -> CountingAlloc {
+            CountingAlloc {
+                allocated: Cell::new(0),
+                deallocated: Cell::new(0),
+            }
+        }
+    }
+
+    #[cfg(feature = "alloc")]
+    unsafe impl Allocator for CountingAlloc {
+        fn allocate(&self, layout: Layout) -> Result<NonNull<u8>, AllocError> {
+            self.allocated.set(self.allocated.get() + 1);
+            Global.allocate(layout)
+        }
+
+        unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+            self.deallocated.set(self.deallocated.get() + 1);
+            Global.deallocate(ptr, layout)
+        }
+    }
+
+    struct FailingAlloc;
+
+    unsafe impl Allocator for FailingAlloc {
+        fn allocate(&self, layout: Layout) -> Result<NonNull<u8>, AllocError> {
+            Err(AllocError::new(layout))
+        }
+
+        unsafe fn deallocate(&self, _: NonNull<u8>, _: Layout) {
+            unreachable!();
+        }
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn test_new_in() {
+        let alloc = CountingAlloc::new();
+
+        let stacked: SmallBox<_, S1, _> = SmallBox::new_in(1234usize, &alloc);
+        assert!(!stacked.is_heap());
+        assert_eq!(*stacked, 1234);
+        assert_eq!(alloc.allocated.get(), 0);
+
+        let heaped: SmallBox<_, S1, _> = SmallBox::new_in((0usize, 1usize), &alloc);
+        assert!(heaped.is_heap());
+        assert_eq!(*heaped, (0, 1));
+        assert_eq!(alloc.allocated.get(), 1);
+
+        let cloned = heaped.clone();
+        assert_eq!(alloc.allocated.get(), 2);
+
+        drop(stacked);
+        drop(heaped);
+        drop(cloned);
+        assert_eq!(alloc.deallocated.get(), 2);
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn test_resize_in() {
+        let alloc = CountingAlloc::new();
+
+        let heaped: SmallBox<_, S1, _> = SmallBox::new_in([1usize, 2], &alloc);
+        assert!(heaped.is_heap());
+        let resized = heaped.resize::<S2>();
+        assert!(resized.is_heap());
+        assert!(ptr::eq(*resized.allocator(), &alloc));
+        assert_eq!(alloc.allocated.get(), 1);
+
+        let other = CountingAlloc::new();
+        let stacked = resized.resize_in::<S2, _>(&other);
+        assert!(!stacked.is_heap());
+        assert_eq!(*stacked, [1, 2]);
+        assert_eq!(alloc.deallocated.get(), 1);
+
+        let heaped = stacked.resize_in::<S1, _>(&other);
+        assert!(heaped.is_heap());
+        assert_eq!(*heaped, [1, 2]);
+        assert_eq!(other.allocated.get(), 1);
+        drop(heaped);
+        assert_eq!(other.deallocated.get(), 1);
+    }
+
+    #[test]
+    fn test_alloc_failure() {
+        let stacked: SmallBox<_, S1, _> = SmallBox::try_new_in(1234usize, FailingAlloc).unwrap();
+        assert!(!stacked.is_heap());
+
+        let err = SmallBox::<_, S1, _>::try_new_in([0usize, 1], FailingAlloc).unwrap_err();
+        assert_eq!(err.layout(), Layout::new::<[usize; 2]>());
+
+        let stacked: SmallBox<_, S2, _> = SmallBox::new_in([0usize, 1], FailingAlloc);
+        let err = stacked.try_resize::<S1>().unwrap_err();
+        assert_eq!(err.layout(), Layout::new::<[usize; 2]>());
+
+        let stacked: SmallBox<_, S2, _> = SmallBox::new_in([0usize, 1], &FailingAlloc);
+        assert_eq!(*stacked.try_clone().unwrap(), [0, 1]);
+    }
+
+    #[test]
+    #[cfg(not(feature = "alloc"))]
+    fn test_no_alloc() {
+        let layout = Layout::new::<[usize; 2]>();
+        assert_eq!(Global.allocate(layout).unwrap_err().layout(), layout);
+
+        let stacked: SmallBox<[usize], S2> = smallbox!([0usize, 1]);
+        assert!(!stacked.is_heap());
+        assert_eq!(*stacked, [0, 1]);
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn test_new_with() {
+        let stacked: SmallBox<_, S1> = SmallBox::new_with(|| 1234usize);
+        assert!(!stacked.is_heap());
+        assert_eq!(*stacked, 1234);
+
+        let heaped: SmallBox<_, S1> = SmallBox::new_with(|| [1u8; 4096]);
+        assert!(heaped.is_heap());
+        assert!(heaped.iter().all(|&b| b == 1));
+
+        let alloc = CountingAlloc::new();
+        let heaped: SmallBox<_, S1, _> = SmallBox::new_with_in(|| (0usize, 1usize), &alloc);
+        assert!(heaped.is_heap());
+        assert_eq!(*heaped, (0, 1));
+        assert_eq!(alloc.allocated.get(), 1);
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    #[deny(unsafe_code)]
+    fn test_smallbox_with() {
+        let stacked: SmallBox<dyn Any, S1> = smallbox_with!(|| 1234usize);
+        assert!(!stacked.is_heap());
+        assert_eq!(stacked.downcast_ref::<usize>(), Some(&1234));
+
+        let heaped: SmallBox<[usize], S1> = smallbox_with!(|| [0usize, 1]);
+        assert!(heaped.is_heap());
+        assert_eq!(*heaped, [0, 1]);
+    }
+
+    #[test]
+    fn test_emplace() {
+        let stacked: SmallBox<[usize; 2], S2> = unsafe {
+            SmallBox::emplace(|slot: &mut MaybeUninit<[usize; 2]>| {
+                slot.as_mut_ptr().write([1, 2]);
+            })
+        };
+        assert!(!stacked.is_heap());
+        assert_eq!(*stacked, [1, 2]);
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_new_with_panic() {
+        use std::panic;
+
+        let alloc = CountingAlloc::new();
+        let result = panic::catch_unwind(panic::AssertUnwindSafe(|| {
+            SmallBox::<[usize; 4], S1, _>::new_with_in(|| panic!("in place"), &alloc)
+        }));
+        assert!(result.is_err());
+        assert_eq!(alloc.allocated.get(), 1);
+        assert_eq!(alloc.deallocated.get(), 1);
+    }
+
+    #[test]
+    fn test_new_uninit() {
+        let mut stacked = SmallBox::<_, S1>::new_uninit();
+        assert!(!stacked.is_heap());
+        stacked.write(1234usize);
+        let stacked = unsafe { stacked.assume_init() };
+        assert_eq!(*stacked, 1234);
+
+        let zeroed = SmallBox::<MaybeUninit<[usize; 2]>, S2>::new_zeroed();
+        assert!(!zeroed.is_heap());
+        assert_eq!(*unsafe { zeroed.assume_init() }, [0, 0]);
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn test_new_uninit_heap() {
+        let mut heaped = SmallBox::<_, S1>::new_uninit();
+        assert!(heaped.is_heap());
+        heaped.write([0usize, 1]);
+        assert_eq!(*unsafe { heaped.assume_init() }, [0, 1]);
+
+        let zeroed = SmallBox::<MaybeUninit<[usize; 2]>, S1>::new_zeroed();
+        assert!(zeroed.is_heap());
+        assert_eq!(*unsafe { zeroed.assume_init() }, [0, 0]);
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn test_new_uninit_slice() {
+        let mut stacked = SmallBox::<[_], S2>::new_uninit_slice(2);
+        assert!(!stacked.is_heap());
+        assert_eq!(stacked.len(), 2);
+        stacked[0].write(1usize);
+        stacked[1].write(2usize);
+        let stacked = unsafe { stacked.assume_init() };
+        assert_eq!(*stacked, [1, 2]);
+
+        let empty = SmallBox::<[MaybeUninit<usize>], S1>::new_zeroed_slice(0);
+        assert_eq!(*unsafe { empty.assume_init() }, []);
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn test_new_uninit_slice_heap() {
+        let alloc = CountingAlloc::new();
+        let zeroed = SmallBox::<[MaybeUninit<u16>], S1, _>::new_zeroed_slice_in(16, &alloc);
+        assert!(zeroed.is_heap());
+        assert_eq!(zeroed.len(), 16);
+        let zeroed = unsafe { zeroed.assume_init() };
+        assert_eq!(*zeroed, [0; 16]);
+        drop(zeroed);
+        assert_eq!(alloc.allocated.get(), 1);
+        assert_eq!(alloc.deallocated.get(), 1);
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn test_from_box() {
+        let boxed: Box<[usize]> = Box::new([0, 1, 2, 3]);
+        let addr = boxed.as_ptr();
+        let heaped: SmallBox<[usize], S1> = SmallBox::from_box(boxed);
+        assert!(heaped.is_heap());
+        assert_eq!(heaped[..].as_ptr(), addr);
+        assert_eq!(*heaped, [0, 1, 2, 3]);
+
+        let boxed: Box<dyn Any> = Box::new(1234usize);
+        let stacked: SmallBox<dyn Any, S1> = SmallBox::from_box_inline(boxed);
+        assert!(!stacked.is_heap());
+        assert_eq!(stacked.downcast_ref::<usize>(), Some(&1234));
+
+        let boxed: Box<[usize]> = Box::new([0, 1]);
+        let heaped: SmallBox<[usize], S1> = SmallBox::from_box_inline(boxed);
+        assert!(heaped.is_heap());
+        assert_eq!(*heaped, [0, 1]);
+
+        let boxed: Box<dyn Any> = Box::new(());
+        let zst: SmallBox<dyn Any, S1> = SmallBox::from_box(boxed);
+        assert!(!zst.is_heap());
+        assert!(zst.is::<()>());
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn test_into_box() {
+        let heaped: SmallBox<[usize], S1> = smallbox!([0, 1, 2, 3]);
+        let addr = heaped[..].as_ptr();
+        let boxed = heaped.into_box();
+        assert_eq!(boxed.as_ptr(), addr);
+        assert_eq!(*boxed, [0, 1, 2, 3]);
+
+        let stacked: SmallBox<dyn Any, S1> = smallbox!(1234usize);
+        let boxed = stacked.into_box();
+        assert_eq!(boxed.downcast_ref::<usize>(), Some(&1234));
+
+        let zst: SmallBox<dyn Any, S1> = smallbox!(());
+        let boxed = zst.into_box();
+        assert!(boxed.is::<()>());
+    }
+
+    #[test]
+    fn test_from_slice() {
+        let stacked: SmallBox<[usize], S2> = SmallBox::from(&[1, 2][..]);
+        assert!(!stacked.is_heap());
+        assert_eq!(*stacked, [1, 2]);
+
+        let empty: SmallBox<[usize], S2> = SmallBox::from(&[][..]);
+        assert!(empty.is_empty());
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn test_from_vec() {
+        let stacked: SmallBox<[usize], S2> = SmallBox::from(vec![1, 2]);
+        assert!(!stacked.is_heap());
+        assert_eq!(*stacked, [1, 2]);
+
+        let heaped: SmallBox<[usize], S2> = SmallBox::from(vec![1, 2, 3]);
+        assert!(heaped.is_heap());
+        assert_eq!(*heaped, [1, 2, 3]);
+
+        let boxes: SmallBox<[_], S2> = SmallBox::from(vec![Box::new(1), Box::new(2)]);
+        assert!(!boxes.is_heap());
+        assert_eq!(*boxes[1], 2);
     }
-}
 
-impl<T: ?Sized, Space> ops::DerefMut for SmallBox<T, Space> {
-    fn deref_mut(&mut self) -> &mut T {
-        unsafe { &mut *self.as_mut_ptr() }
-    }
-}
+    #[test]
+    fn test_from_str() {
+        let stacked: SmallBox<str, S2> = SmallBox::from("small");
+        assert!(!stacked.is_heap());
+        assert_eq!(&*stacked, "small");
 
-impl<T: ?Sized, Space> ops::Drop for SmallBox<T, Space> {
-    fn drop(&mut self) {
-        unsafe {
-            let layout = Layout::for_value::<T>(&*self);
-            ptr::drop_in_place::<T>(&mut **self);
-            if self.is_heap() {
-                alloc::dealloc(self.ptr.cast(), layout);
-            }
-        }
+        let c: SmallBox<str, S1> = SmallBox::from('ß');
+        assert!(!c.is_heap());
+        assert_eq!(&*c, "ß");
     }
-}
 
-impl<T: Clone, Space> Clone for SmallBox<T, Space>
-where T: Sized
-{
-    fn clone(&self) -> Self {
-        let val: &T = self;
-        SmallBox::new(val.clone())
-    }
-}
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn test_from_string() {
+        let heaped: SmallBox<str, S1> = SmallBox::from("a longer string");
+        assert!(heaped.is_heap());
+        assert_eq!(&*heaped, "a longer string");
 
-impl<T: ?Sized + fmt::Display, Space> fmt::Display for SmallBox<T, Space> {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        fmt::Display::fmt(&**self, f)
+        let stacked: SmallBox<str, S1> = SmallBox::from("abc".to_string());
+        assert!(!stacked.is_heap());
+        assert_eq!(&*stacked, "abc");
     }
-}
 
-impl<T: ?Sized + fmt::Debug, Space> fmt::Debug for SmallBox<T, Space> {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        fmt::Debug::fmt(&**self, f)
-    }
-}
+    #[test]
+    fn test_from_iter() {
+        let stacked: SmallBox<[usize], S4> = (0..4).collect();
+        assert!(!stacked.is_heap());
+        assert_eq!(*stacked, [0, 1, 2, 3]);
 
-impl<T: ?Sized, Space> fmt::Pointer for SmallBox<T, Space> {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        // It's not possible to extract the inner Unique directly from the Box,
-        // instead we cast it to a *const which aliases the Unique
-        let ptr: *const T = &**self;
-        fmt::Pointer::fmt(&ptr, f)
+        let zst: SmallBox<[()], S1> = core::iter::repeat(()).take(100).collect();
+        assert!(!zst.is_heap());
+        assert_eq!(zst.len(), 100);
     }
-}
 
-impl<T: ?Sized + PartialEq, Space> PartialEq for SmallBox<T, Space> {
-    fn eq(&self, other: &SmallBox<T, Space>) -> bool {
-        PartialEq::eq(&**self, &**other)
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn test_from_iter_heap() {
+        let heaped: SmallBox<[usize], S4> = (0..5).collect();
+        assert!(heaped.is_heap());
+        assert_eq!(*heaped, [0, 1, 2, 3, 4]);
+
+        let heaped: SmallBox<[u8], S1> = (0..100).collect();
+        assert!(heaped.is_heap());
+        assert_eq!(heaped.len(), 100);
+        assert_eq!(heaped[99], 99);
     }
-}
 
-impl<T: ?Sized + PartialOrd, Space> PartialOrd for SmallBox<T, Space> {
-    fn partial_cmp(&self, other: &SmallBox<T, Space>) -> Option<Ordering> {
-        PartialOrd::partial_cmp(&**self, &**other)
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_from_iter_panic() {
+        use std::panic;
+        use std::rc::Rc;
+
+        let rc = Rc::new(());
+        let result = panic::catch_unwind(panic::AssertUnwindSafe(|| {
+            let _: SmallBox<[Rc<()>], S4> = (0..3)
+                .map(|i| if i < 2 { rc.clone() } else { panic!("stop") })
+                .collect();
+        }));
+        assert!(result.is_err());
+        assert_eq!(Rc::strong_count(&rc), 1);
     }
-    fn lt(&self, other: &SmallBox<T, Space>) -> bool {
-        PartialOrd::lt(&**self, &**other)
+
+    #[test]
+    fn test_iterator() {
+        let mut iter: SmallBox<dyn DoubleEndedIterator<Item = usize>, S4> = smallbox!(0..10);
+        assert!(!iter.is_heap());
+        assert_eq!(iter.size_hint(), (10, Some(10)));
+        assert_eq!(iter.next(), Some(0));
+        assert_eq!(iter.nth(2), Some(3));
+        assert_eq!(iter.next_back(), Some(9));
+        assert_eq!(iter.nth_back(1), Some(7));
+        assert_eq!(iter.sum::<usize>(), 4 + 5 + 6);
+
+        let iter: SmallBox<dyn ExactSizeIterator<Item = u8>, S4> = smallbox!(1u8..4);
+        assert_eq!(iter.len(), 3);
+        assert_eq!(iter.collect::<SmallBox<[u8], S1>>()[..], [1, 2, 3]);
     }
-    fn le(&self, other: &SmallBox<T, Space>) -> bool {
-        PartialOrd::le(&**self, &**other)
+
+    fn noop_waker() -> Waker {
+        fn clone(_: *const ()) -> RawWaker {
+            RawWaker::new(ptr::null(), &VTABLE)
+        }
+        fn noop(_: *const ()) {}
+        static VTABLE: RawWakerVTable = RawWakerVTable::new(clone, noop, noop, noop);
+
+        unsafe { Waker::from_raw(clone(ptr::null())) }
     }
-    fn ge(&self, other: &SmallBox<T, Space>) -> bool {
-        PartialOrd::ge(&**self, &**other)
+
+    #[test]
+    fn test_future() {
+        let waker = noop_waker();
+        let mut cx = Context::from_waker(&waker);
+
+        let mut fut: SmallBox<dyn Future<Output = usize> + Unpin, S2> =
+            smallbox!(core::future::ready(42));
+        assert!(!fut.is_heap());
+        assert_eq!(Pin::new(&mut fut).poll(&mut cx), Poll::Ready(42));
     }
-    fn gt(&self, other: &SmallBox<T, Space>) -> bool {
-        PartialOrd::gt(&**self, &**other)
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn test_pin() {
+        let waker = noop_waker();
+        let mut cx = Context::from_waker(&waker);
+
+        let mut pinned: Pin<SmallBox<_, S4>> = SmallBox::pin(async { 42 });
+        assert_eq!(pinned.as_mut().poll(&mut cx), Poll::Ready(42));
+
+        let fut: SmallBox<dyn Future<Output = usize>, S4> = smallbox!(async { 42 });
+        assert!(!fut.is_heap());
+        let mut pinned = fut.into_pin();
+        assert_eq!(pinned.as_mut().poll(&mut cx), Poll::Ready(42));
+        assert!(unsafe { Pin::into_inner_unchecked(pinned) }.is_heap());
+
+        let heaped: SmallBox<[usize], S1> = smallbox!([0usize, 1]);
+        let addr = heaped[..].as_ptr();
+        let pinned = heaped.into_pin();
+        assert_eq!(pinned.as_ptr(), addr);
     }
-}
 
-impl<T: ?Sized + Ord, Space> Ord for SmallBox<T, Space> {
-    fn cmp(&self, other: &SmallBox<T, Space>) -> Ordering {
-        Ord::cmp(&**self, &**other)
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn test_pin_zst() {
+        use core::marker::PhantomPinned;
+
+        #[repr(align(16))]
+        struct Pinned(PhantomPinned);
+
+        fn addr<T>(pinned: &Pin<SmallBox<T, S1>>) -> *const T {
+            &**pinned
+        }
+
+        let pinned: Pin<SmallBox<_, S1>> = SmallBox::pin(Pinned(PhantomPinned));
+        let before = addr(&pinned);
+        assert_eq!(before.cast::<u8>().align_offset(16), 0);
+        let mut slots = [None, None];
+        slots[1] = Some(pinned);
+        assert_eq!(addr(slots[1].as_ref().unwrap()), before);
+
+        let stacked: SmallBox<_, S1> = SmallBox::new(Pinned(PhantomPinned));
+        assert!(!stacked.is_heap());
+        let pinned = stacked.into_pin();
+        let before = addr(&pinned);
+        let mut slots = [None, None];
+        slots[1] = Some(pinned);
+        assert_eq!(addr(slots[1].as_ref().unwrap()), before);
     }
-}
 
-impl<T: ?Sized + Eq, Space> Eq for SmallBox<T, Space> {}
+    #[test]
+    fn test_as_ref_borrow() {
+        use core::borrow::Borrow;
+        use core::borrow::BorrowMut;
+
+        let mut stacked: SmallBox<[usize], S2> = smallbox!([1usize, 2]);
+        assert_eq!(AsRef::<[usize]>::as_ref(&stacked), [1, 2]);
+        AsMut::<[usize]>::as_mut(&mut stacked)[0] = 3;
+        assert_eq!(Borrow::<[usize]>::borrow(&stacked), [3, 2]);
+        BorrowMut::<[usize]>::borrow_mut(&mut stacked)[1] = 4;
+        assert_eq!(*stacked, [3, 4]);
+    }
 
-impl<T: ?Sized + Hash, Space> Hash for SmallBox<T, Space> {
-    fn hash<H: hash::Hasher>(&self, state: &mut H) {
-        (**self).hash(state);
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_borrow_map_key() {
+        use std::collections::HashMap;
+
+        let mut map: HashMap<SmallBox<str, S2>, usize> = HashMap::new();
+        map.insert(SmallBox::from("one"), 1);
+        map.insert(SmallBox::from("a longer key"), 2);
+        assert_eq!(map.get("one"), Some(&1));
+        assert_eq!(map.get("a longer key"), Some(&2));
     }
-}
 
-unsafe impl<T: ?Sized + Send, Space> Send for SmallBox<T, Space> {}
-unsafe impl<T: ?Sized + Sync, Space> Sync for SmallBox<T, Space> {}
+    #[test]
+    fn test_from_default() {
+        let stacked: SmallBox<usize, S1> = 42.into();
+        assert_eq!(*stacked, 42);
 
-#[cfg(test)]
-mod tests {
-    use core::any::Any;
-    use core::ptr::addr_of;
+        let zero: SmallBox<usize, S1> = Default::default();
+        assert_eq!(*zero, 0);
 
-    use ::alloc::boxed::Box;
-    use ::alloc::vec;
+        let empty: SmallBox<[usize], S1> = Default::default();
+        assert!(empty.is_empty());
+        assert!(!empty.is_heap());
 
-    use super::SmallBox;
-    use crate::space::*;
+        let empty: SmallBox<str, S1> = Default::default();
+        assert_eq!(&*empty, "");
+    }
 
     #[test]
-    fn test_basic() {
-        let stacked: SmallBox<usize, S1> = SmallBox::new(1234usize);
-        assert!(*stacked == 1234);
+    fn test_auto_traits() {
+        use core::marker::PhantomPinned;
+        use core::panic::RefUnwindSafe;
+        use core::panic::UnwindSafe;
+
+        fn assert_unpin<T: Unpin>() {}
+        fn assert_unwind_safe<T: UnwindSafe + RefUnwindSafe>() {}
+
+        assert_unpin::<SmallBox<usize, PhantomPinned>>();
+        assert_unpin::<SmallBox<dyn Future<Output = ()> + Unpin, S1>>();
+        assert_unwind_safe::<SmallBox<usize, S1>>();
+        assert_unwind_safe::<SmallBox<[usize], S1>>();
+    }
 
-        let heaped: SmallBox<(usize, usize), S1> = SmallBox::new((0, 1));
-        assert!(*heaped == (0, 1));
+    #[test]
+    fn test_smallbox_coerce() {
+        let stacked: SmallBox<[usize; 2], S2> = SmallBox::new([1, 2]);
+        let slice: SmallBox<[usize], S2> = smallbox_coerce!(stacked);
+        assert!(!slice.is_heap());
+        assert_eq!(*slice, [1, 2]);
+
+        let num: SmallBox<usize, S1> = SmallBox::new(42);
+        let any: SmallBox<dyn Any, S1> = unsafe { num.coerce(|ptr| -> *const dyn Any { ptr }) };
+        assert_eq!(any.downcast_ref::<usize>(), Some(&42));
     }
 
+    #[cfg(feature = "alloc")]
     #[test]
-    fn test_new_unchecked() {
-        let val = [0usize, 1];
-        let ptr = addr_of!(val);
+    fn test_smallbox_coerce_heap() {
+        let heaped: SmallBox<[usize; 4], S1> = SmallBox::new([1, 2, 3, 4]);
+        let addr = (*heaped).as_ptr();
+        let slice: SmallBox<[usize], S1> = smallbox_coerce!(heaped);
+        assert!(slice.is_heap());
+        assert_eq!(slice[..].as_ptr(), addr);
+        assert_eq!(*slice, [1, 2, 3, 4]);
+    }
 
-        unsafe {
-            let stacked: SmallBox<[usize], S2> = SmallBox::new_unchecked(val, ptr);
-            assert!(*stacked == [0, 1]);
-            assert!(!stacked.is_heap());
-        }
+    trait Super {
+        fn name(&self) -> &'static str;
+    }
 
-        let val = [0usize, 1, 2];
-        let ptr = addr_of!(val);
+    trait Sub: Super + Any {
+        fn as_any(&self) -> &dyn Any;
+    }
 
-        unsafe {
-            let heaped: SmallBox<dyn Any, S2> = SmallBox::new_unchecked(val, ptr);
-            assert!(heaped.is_heap());
+    struct Named([usize; 2]);
 
-            if let Some(array) = heaped.downcast_ref::<[usize; 3]>() {
-                assert_eq!(*array, [0, 1, 2]);
-            } else {
-                unreachable!();
-            }
+    impl Super for Named {
+        fn name(&self) -> &'static str {
+            "named"
         }
     }
 
-    #[test]
-    #[deny(unsafe_code)]
-    fn test_macro() {
-        let stacked: SmallBox<dyn Any, S1> = smallbox!(1234usize);
-        if let Some(num) = stacked.downcast_ref::<usize>() {
-            assert_eq!(*num, 1234);
-        } else {
-            unreachable!();
+    impl Sub for Named {
+        fn as_any(&self) -> &dyn Any {
+            self
         }
+    }
 
-        let heaped: SmallBox<dyn Any, S1> = smallbox!([0usize, 1]);
-        if let Some(array) = heaped.downcast_ref::<[usize; 2]>() {
-            assert_eq!(*array, [0, 1]);
-        } else {
-            unreachable!();
-        }
+    // trait upcasting coercion needs rust 1.86+, detected by the build script
+    #[cfg(trait_upcasting)]
+    #[test]
+    fn test_upcast() {
+        let sub: SmallBox<dyn Sub, S2> = smallbox!(Named([1, 2]));
+        let sup: SmallBox<dyn Super, S2> = smallbox_coerce!(sub);
+        assert!(!sup.is_heap());
+        assert_eq!(sup.name(), "named");
+
+        let sub: SmallBox<dyn Sub, S2> = smallbox!(Named([3, 4]));
+        let any: SmallBox<dyn Any, S2> = smallbox_coerce!(sub);
+        assert_eq!(any.downcast_ref::<Named>().unwrap().0, [3, 4]);
+    }
 
-        let is_even: SmallBox<dyn Fn(u8) -> bool, S1> = smallbox!(|num: u8| num % 2 == 0);
-        assert!(!is_even(5));
-        assert!(is_even(6));
+    #[test]
+    fn test_downcast_unchecked() {
+        let stacked: SmallBox<dyn Any + Send + Sync, S2> = smallbox!([1usize, 2]);
+        let array = unsafe { stacked.downcast_unchecked::<[usize; 2]>() };
+        assert!(!array.is_heap());
+        assert_eq!(*array, [1, 2]);
     }
 
+    #[cfg(feature = "alloc")]
     #[test]
-    #[cfg(feature = "coerce")]
-    fn test_coerce() {
-        let stacked: SmallBox<dyn Any, S1> = SmallBox::new(1234usize);
-        if let Some(num) = stacked.downcast_ref::<usize>() {
-            assert_eq!(*num, 1234);
-        } else {
-            unreachable!();
-        }
+    fn test_downcast_unchecked_heap() {
+        let heaped: SmallBox<dyn Any + Send + Sync, S1> = smallbox!([1usize, 2]);
+        let addr = crate::sptr::from_ref(&*heaped).cast::<u8>();
+        let array = unsafe { heaped.downcast_unchecked::<[usize; 2]>() };
+        assert!(array.is_heap());
+        assert_eq!((*array).as_ptr().cast::<u8>(), addr);
+        assert_eq!(*array, [1, 2]);
+    }
 
-        let heaped: SmallBox<dyn Any, S1> = SmallBox::new([0usize, 1]);
-        if let Some(array) = heaped.downcast_ref::<[usize; 2]>() {
-            assert_eq!(*array, [0, 1]);
-        } else {
-            unreachable!();
-        }
+    #[cfg(all(feature = "alloc", trait_upcasting))]
+    #[test]
+    fn test_upcast_heap() {
+        let sub: SmallBox<dyn Sub, S1> = smallbox!(Named([1, 2]));
+        let addr = crate::sptr::from_ref(&*sub).cast::<u8>();
+        let sup: SmallBox<dyn Super, S1> = smallbox_coerce!(sub);
+        assert!(sup.is_heap());
+        assert_eq!(crate::sptr::from_ref(&*sup).cast::<u8>(), addr);
+        assert_eq!(sup.name(), "named");
     }
 
     #[test]
-    fn test_drop() {
-        use core::cell::Cell;
+    fn test_downcast_with() {
+        let sub: SmallBox<dyn Sub, S2> = smallbox!(Named([1, 2]));
+        let sub = match unsafe { sub.downcast_with::<usize, _>(|val| val.as_any()) } {
+            Ok(_) => unreachable!(),
+            Err(sub) => sub,
+        };
+        assert_eq!(sub.name(), "named");
+        let named = unsafe { sub.downcast_with::<Named, _>(|val| val.as_any()) }
+            .ok()
+            .unwrap();
+        assert!(!named.is_heap());
+        assert_eq!(named.0, [1, 2]);
+    }
 
-        #[allow(dead_code)]
-        struct Struct<'a>(&'a Cell<bool>, u8);
-        impl<'a> Drop for Struct<'a> {
-            fn drop(&mut self) {
-                self.0.set(true);
-            }
-        }
+    #[test]
+    fn test_downcast_dyn() {
+        let sub: SmallBox<dyn Sub, S2> = smallbox!(Named([1, 2]));
+        let sub = match sub.downcast_dyn::<usize>() {
+            Ok(_) => unreachable!(),
+            Err(sub) => sub,
+        };
+        let named = sub.downcast_dyn::<Named>().ok().unwrap();
+        assert!(!named.is_heap());
+        assert_eq!(named.0, [1, 2]);
 
-        let flag = Cell::new(false);
-        let stacked: SmallBox<_, S2> = SmallBox::new(Struct(&flag, 0));
-        assert!(!stacked.is_heap());
-        assert!(!flag.get());
-        drop(stacked);
-        assert!(flag.get());
+        // without an `Any` supertrait, the type is the trait object itself
+        let sup: SmallBox<dyn Super, S2> = smallbox!(Named([1, 2]));
+        let sup = sup.downcast_dyn::<Named>().err().unwrap();
+        assert_eq!(sup.name(), "named");
 
-        let flag = Cell::new(false);
-        let heaped: SmallBox<_, S1> = SmallBox::new(Struct(&flag, 0));
-        assert!(heaped.is_heap());
-        assert!(!flag.get());
-        drop(heaped);
-        assert!(flag.get());
+        let num: SmallBox<usize, S1> = SmallBox::new(3);
+        assert_eq!(*num.downcast_dyn::<usize>().ok().unwrap(), 3);
     }
 
+    #[cfg(feature = "alloc")]
     #[test]
-    fn test_dont_drop_space() {
-        #[allow(dead_code)]
-        struct NoDrop(S1);
-        impl Drop for NoDrop {
-            fn drop(&mut self) {
-                unreachable!();
-            }
-        }
+    fn test_downcast_dyn_heap() {
+        let sub: SmallBox<dyn Sub, S1> = smallbox!(Named([1, 2]));
+        let addr = crate::sptr::from_ref(&*sub).cast::<u8>();
+        let named = sub.downcast_dyn::<Named>().ok().unwrap();
+        assert!(named.is_heap());
+        assert_eq!(crate::sptr::from_ref(&*named).cast::<u8>(), addr);
+        assert_eq!(named.0, [1, 2]);
+    }
 
-        drop(SmallBox::<_, NoDrop>::new([true]));
+    #[test]
+    fn test_clone() {
+        let stacked: SmallBox<[usize; 2], S2> = smallbox!([1usize, 2]);
+        assert_eq!(stacked, stacked.clone())
     }
 
     #[test]
-    fn test_oversize() {
-        let fit = SmallBox::<_, S1>::new([1usize]);
-        let oversize = SmallBox::<_, S1>::new([1usize, 2]);
-        assert!(!fit.is_heap());
-        assert!(oversize.is_heap());
+    fn test_clone_unsized() {
+        let slice: SmallBox<[usize], S2> = smallbox!([1usize, 2]);
+        let cloned = slice.clone();
+        assert!(!cloned.is_heap());
+        assert_eq!(*cloned, [1, 2]);
+
+        let s: SmallBox<str, S2> = SmallBox::from("small");
+        let cloned = s.clone();
+        assert!(!cloned.is_heap());
+        assert_eq!(&*cloned, "small");
+
+        trait Value: SmallClone {
+            fn get(&self) -> usize;
+        }
+
+        #[derive(Clone)]
+        struct Num(usize);
+
+        impl Value for Num {
+            fn get(&self) -> usize {
+                self.0
+            }
+        }
+
+        let dyn_val: SmallBox<dyn Value, S1> = smallbox!(Num(42));
+        let cloned = dyn_val.clone();
+        assert!(!cloned.is_heap());
+        assert_eq!(cloned.get(), 42);
     }
 
+    #[cfg(feature = "alloc")]
     #[test]
-    fn test_resize() {
-        let m = SmallBox::<_, S4>::new([1usize, 2]);
-        let l = m.resize::<S8>();
-        assert!(!l.is_heap());
-        let m = l.resize::<S4>();
-        assert!(!m.is_heap());
-        let s = m.resize::<S2>();
-        assert!(!s.is_heap());
-        let xs = s.resize::<S1>();
-        assert!(xs.is_heap());
-        let m = xs.resize::<S4>();
-        assert!(m.is_heap());
-        assert_eq!(*m, [1usize, 2]);
+    fn test_clone_unsized_heap() {
+        let slice: SmallBox<[String], S2> = SmallBox::from(vec!["a".to_string(); 4]);
+        let cloned = slice.clone();
+        assert!(cloned.is_heap());
+        assert_eq!(*cloned, *slice);
+
+        let s: SmallBox<str, S1> = SmallBox::from("a longer string");
+        let cloned = s.try_clone().unwrap();
+        assert!(cloned.is_heap());
+        assert_eq!(&*cloned, "a longer string");
+
+        let alloc = CountingAlloc::new();
+        let dyn_val: SmallBox<dyn SmallClone, S1, _> = unsafe {
+            let val = [0usize; 4];
+            SmallBox::new_unchecked_in(val, addr_of!(val), &alloc)
+        };
+        let cloned = dyn_val.clone();
+        assert!(cloned.is_heap());
+        assert_eq!(alloc.allocated.get(), 2);
     }
 
+    #[cfg(feature = "std")]
     #[test]
-    fn test_clone() {
-        let stacked: SmallBox<[usize; 2], S2> = smallbox!([1usize, 2]);
-        assert_eq!(stacked, stacked.clone())
+    fn test_clone_slice_panic() {
+        use std::panic;
+        use std::rc::Rc;
+
+        struct Bomb(Rc<()>, bool);
+
+        impl Clone for Bomb {
+            fn clone(&self) -> Self {
+                assert!(!self.1, "boom");
+                Bomb(self.0.clone(), false)
+            }
+        }
+
+        let rc = Rc::new(());
+        let slice: SmallBox<[Bomb], S4> = smallbox!([
+            Bomb(rc.clone(), false),
+            Bomb(rc.clone(), false),
+            Bomb(rc.clone(), true)
+        ]);
+        let result = panic::catch_unwind(panic::AssertUnwindSafe(|| slice.clone()));
+        assert!(result.is_err());
+        assert_eq!(Rc::strong_count(&rc), 4);
     }
 
     #[test]
@@ -599,6 +3162,13 @@ mod tests {
 
         let zst: SmallBox<[usize], ZSpace> = smallbox!([1usize; 0]);
         assert_eq!(*zst, [1usize; 0]);
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn test_zst_heap() {
+        struct ZSpace;
+
         let zst: SmallBox<[usize], ZSpace> = smallbox!([1usize; 2]);
         assert_eq!(*zst, [1usize; 2]);
     }
@@ -609,6 +3179,23 @@ mod tests {
         assert!(!stacked.is_heap());
         assert_eq!(SmallBox::new(0x01), stacked.downcast::<u32>().unwrap());
 
+        let stacked_send: SmallBox<dyn Any + Send, S1> = smallbox!(0x01u32);
+        assert!(!stacked_send.is_heap());
+        assert_eq!(SmallBox::new(0x01), stacked_send.downcast::<u32>().unwrap());
+
+        let stacked_sync: SmallBox<dyn Any + Send + Sync, S1> = smallbox!(0x01u32);
+        assert!(!stacked_sync.is_heap());
+        assert_eq!(SmallBox::new(0x01), stacked_sync.downcast::<u32>().unwrap());
+
+        let mismatched: SmallBox<dyn Any, S1> = smallbox!(0x01u32);
+        assert!(mismatched.downcast::<u8>().is_err());
+        let mismatched: SmallBox<dyn Any, S1> = smallbox!(0x01u32);
+        assert!(mismatched.downcast::<u64>().is_err());
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn test_downcast_heap() {
         let heaped: SmallBox<dyn Any, S1> = smallbox!([1usize, 2]);
         assert!(heaped.is_heap());
         assert_eq!(
@@ -616,35 +3203,53 @@ mod tests {
             heaped.downcast::<[usize; 2]>().unwrap()
         );
 
-        let stacked_send: SmallBox<dyn Any + Send, S1> = smallbox!(0x01u32);
-        assert!(!stacked_send.is_heap());
-        assert_eq!(SmallBox::new(0x01), stacked_send.downcast::<u32>().unwrap());
-
         let heaped_send: SmallBox<dyn Any + Send, S1> = smallbox!([1usize, 2]);
         assert!(heaped_send.is_heap());
         assert_eq!(
             SmallBox::new([1usize, 2]),
             heaped_send.downcast::<[usize; 2]>().unwrap()
         );
-
-        let mismatched: SmallBox<dyn Any, S1> = smallbox!(0x01u32);
-        assert!(mismatched.downcast::<u8>().is_err());
-        let mismatched: SmallBox<dyn Any, S1> = smallbox!(0x01u32);
-        assert!(mismatched.downcast::<u64>().is_err());
     }
 
+    #[cfg(feature = "alloc")]
     #[test]
     fn test_option_encoding() {
         let tester: SmallBox<Box<()>, S2> = SmallBox::new(Box::new(()));
         assert!(Some(tester).is_some());
     }
 
+    #[test]
+    fn test_can_allocate() {
+        assert_eq!(Global::CAN_ALLOCATE, cfg!(feature = "alloc"));
+        assert_eq!(<&Global>::CAN_ALLOCATE, Global::CAN_ALLOCATE);
+    }
+
+    #[test]
+    fn test_option_size() {
+        assert_eq!(
+            mem::size_of::<Option<SmallBox<usize, S2>>>(),
+            mem::size_of::<SmallBox<usize, S2>>()
+        );
+        assert_eq!(
+            mem::size_of::<Option<SmallBox<dyn Any, S64>>>(),
+            mem::size_of::<SmallBox<dyn Any, S64>>()
+        );
+        assert_eq!(
+            mem::size_of::<Option<SmallBox<[u8], [u8; 3]>>>(),
+            mem::size_of::<SmallBox<[u8], [u8; 3]>>()
+        );
+    }
+
     #[test]
     fn test_into_inner() {
         let tester: SmallBox<_, S1> = SmallBox::new([21usize]);
         let val = tester.into_inner();
         assert_eq!(val[0], 21);
+    }
 
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn test_into_inner_heap() {
         let tester: SmallBox<_, S1> = SmallBox::new(vec![21, 56, 420]);
         let val = tester.into_inner();
         assert_eq!(val[1], 56);
@@ -0,0 +1,442 @@
+use core::fmt;
+use core::mem::ManuallyDrop;
+use core::ptr;
+
+use crate::SmallBox;
+
+macro_rules! small_fn {
+    ($(#[$attr:meta])* $name:ident, $call:ident $(, $bound:ident)*) => {
+        $(#[$attr])*
+        pub struct $name<'a, Args, R, Space> {
+            inner: SmallBox<dyn $call<Args, R> $(+ $bound)* + 'a, Space>,
+        }
+
+        impl<'a, Args, R, Space> $name<'a, Args, R, Space> {
+            /// Box the closure on stack or on heap depending on its size.
+            ///
+            /// `F` is a closure taking the arguments in the tuple `Args` and returning `R`.
+            #[inline]
+            #[track_caller]
+            pub fn new<F>(f: F) -> Self
+            where F: $call<Args, R> $(+ $bound)* + 'a {
+                let ptr: *const (dyn $call<Args, R> $(+ $bound)* + 'a) = ptr::addr_of!(f);
+                $name {
+                    inner: unsafe { SmallBox::new_unchecked(f, ptr) },
+                }
+            }
+
+            /// Returns true if the closure is stored on heap.
+            #[inline]
+            pub fn is_heap(&self) -> bool {
+                self.inner.is_heap()
+            }
+        }
+
+        impl<'a, Args, R, Space> fmt::Debug for $name<'a, Args, R, Space> {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.debug_struct(stringify!($name))
+                    .field("is_heap", &self.is_heap())
+                    .finish()
+            }
+        }
+    };
+}
+
+small_fn!(
+    /// A boxed `FnOnce` closure that can be called by value on stable rust
+    ///
+    /// `Box<dyn FnOnce()>` can be called directly, but calling an unsized `FnOnce` by value
+    /// is not possible for other types on stable rust. `SmallFnOnce` stores the closure in a
+    /// `SmallBox` and calls it exactly once with [`call`](SmallFnOnce::call), freeing the heap
+    /// memory, if any, afterwards.
+    ///
+    /// `Args` is the tuple of the argument types, e.g. `SmallFnOnce<(u8, &str), bool, S4>`
+    /// boxes a `FnOnce(u8, &str) -> bool`. Closures with up to eight arguments are supported.
+    /// The argument types of the closure are not inferred from `Args`, so annotate them.
+    /// The closure may borrow data that outlives `'a`.
+    ///
+    /// `SmallFnOnce` is never `Send`, see [`SendSmallFnOnce`] for closures that are.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use smallbox::space::S4;
+    /// use smallbox::SmallFnOnce;
+    ///
+    /// let name = String::from("smallbox");
+    /// let greet: SmallFnOnce<(&str,), String, S4> =
+    ///     SmallFnOnce::new(|greeting: &str| format!("{}, {}!", greeting, name));
+    ///
+    /// assert!(!greet.is_heap());
+    /// assert_eq!(greet.call("Hello"), "Hello, smallbox!");
+    /// ```
+    SmallFnOnce,
+    CallOnce
+);
+
+small_fn!(
+    /// A boxed `FnMut` closure
+    ///
+    /// The mutable counterpart of [`SmallFnOnce`], called any number of times with
+    /// [`call`](SmallFnMut::call).
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use smallbox::space::S1;
+    /// use smallbox::SmallFnMut;
+    ///
+    /// let mut count = 0;
+    /// let mut counter: SmallFnMut<(), usize, S1> = SmallFnMut::new(|| {
+    ///     count += 1;
+    ///     count
+    /// });
+    ///
+    /// assert_eq!(counter.call(), 1);
+    /// assert_eq!(counter.call(), 2);
+    /// drop(counter);
+    /// assert_eq!(count, 2);
+    /// ```
+    SmallFnMut,
+    CallMut
+);
+
+small_fn!(
+    /// A boxed `Fn` closure
+    ///
+    /// The shared counterpart of [`SmallFnOnce`], called any number of times through a
+    /// shared reference with [`call`](SmallFn::call).
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use smallbox::space::S1;
+    /// use smallbox::SmallFn;
+    ///
+    /// let is_even: SmallFn<(u8,), bool, S1> = SmallFn::new(|num: u8| num % 2 == 0);
+    ///
+    /// assert!(is_even.call(6));
+    /// assert!(!is_even.call(7));
+    /// ```
+    SmallFn,
+    CallFn
+);
+
+small_fn!(
+    /// A boxed `FnOnce + Send` closure
+    ///
+    /// The `Send` counterpart of [`SmallFnOnce`], which can be moved to another thread,
+    /// e.g. through the task queue of an event loop.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::sync::mpsc;
+    /// use std::thread;
+    ///
+    /// use smallbox::space::S4;
+    /// use smallbox::SendSmallFnOnce;
+    ///
+    /// let (tx, rx) = mpsc::channel::<SendSmallFnOnce<'static, (), usize, S4>>();
+    /// let worker = thread::spawn(move || rx.iter().map(|task| task.call()).sum::<usize>());
+    ///
+    /// for i in 0..4usize {
+    ///     tx.send(SendSmallFnOnce::new(move || i * 2)).unwrap();
+    /// }
+    /// drop(tx);
+    ///
+    /// assert_eq!(worker.join().unwrap(), 12);
+    /// ```
+    SendSmallFnOnce,
+    CallOnce,
+    Send
+);
+
+small_fn!(
+    /// A boxed `FnMut + Send` closure
+    ///
+    /// The `Send` counterpart of [`SmallFnMut`].
+    SendSmallFnMut,
+    CallMut,
+    Send
+);
+
+small_fn!(
+    /// A boxed `Fn + Send` closure
+    ///
+    /// The `Send` counterpart of [`SmallFn`].
+    SendSmallFn,
+    CallFn,
+    Send
+);
+
+mod sealed {
+    pub trait Sealed<Args, R> {}
+}
+
+/// A closure that can be boxed in a [`SmallFnOnce`]
+///
+/// This trait is sealed, it is implemented for every `FnOnce` closure taking the
+/// arguments in the tuple `Args` and returning `R`, and can't be implemented otherwise.
+pub trait CallOnce<Args, R>: sealed::Sealed<Args, R> {
+    /// Move the closure out of `self` and call it.
+    ///
+    /// # Safety
+    ///
+    /// The closure must not be used or dropped afterwards.
+    unsafe fn call_once(&mut self, args: Args) -> R;
+}
+
+/// A closure that can be boxed in a [`SmallFnMut`]
+///
+/// This trait is sealed, it is implemented for every `FnMut` closure taking the
+/// arguments in the tuple `Args` and returning `R`, and can't be implemented otherwise.
+pub trait CallMut<Args, R>: sealed::Sealed<Args, R> {
+    /// Call the closure.
+    fn call_mut(&mut self, args: Args) -> R;
+}
+
+/// A closure that can be boxed in a [`SmallFn`]
+///
+/// This trait is sealed, it is implemented for every `Fn` closure taking the
+/// arguments in the tuple `Args` and returning `R`, and can't be implemented otherwise.
+pub trait CallFn<Args, R>: sealed::Sealed<Args, R> {
+    /// Call the closure.
+    fn call(&self, args: Args) -> R;
+}
+
+/// Frees the storage of a called closure, also if the closure panics.
+struct DeallocOnDrop<T: ?Sized, Space>(ManuallyDrop<SmallBox<T, Space>>);
+
+impl<T: ?Sized, Space> Drop for DeallocOnDrop<T, Space> {
+    fn drop(&mut self) {
+        unsafe { ManuallyDrop::take(&mut self.0).dealloc_without_drop() }
+    }
+}
+
+macro_rules! impl_call {
+    ($once:ident, $mut:ident, $fn:ident; $($arg:ident $val:ident),*) => {
+        impl<'a, $($arg,)* R, Space> $once<'a, ($($arg,)*), R, Space> {
+            /// Call the closure, consuming it.
+            #[inline]
+            #[allow(clippy::too_many_arguments)]
+            pub fn call(self, $($val: $arg),*) -> R {
+                let mut guard = DeallocOnDrop(ManuallyDrop::new(self.inner));
+                unsafe { guard.0.call_once(($($val,)*)) }
+            }
+        }
+
+        impl<'a, $($arg,)* R, Space> $mut<'a, ($($arg,)*), R, Space> {
+            /// Call the closure.
+            #[inline]
+            #[allow(clippy::too_many_arguments)]
+            pub fn call(&mut self, $($val: $arg),*) -> R {
+                self.inner.call_mut(($($val,)*))
+            }
+        }
+
+        impl<'a, $($arg,)* R, Space> $fn<'a, ($($arg,)*), R, Space> {
+            /// Call the closure.
+            #[inline]
+            #[allow(clippy::too_many_arguments)]
+            pub fn call(&self, $($val: $arg),*) -> R {
+                self.inner.call(($($val,)*))
+            }
+        }
+    };
+}
+
+macro_rules! impl_small_fn {
+    ($($arg:ident $val:ident),*) => {
+        impl<F, $($arg,)* R> sealed::Sealed<($($arg,)*), R> for F
+        where F: FnOnce($($arg),*) -> R
+        {
+        }
+
+        impl<F, $($arg,)* R> CallOnce<($($arg,)*), R> for F
+        where F: FnOnce($($arg),*) -> R
+        {
+            #[inline]
+            unsafe fn call_once(&mut self, ($($val,)*): ($($arg,)*)) -> R {
+                ptr::read(self)($($val),*)
+            }
+        }
+
+        impl<F, $($arg,)* R> CallMut<($($arg,)*), R> for F
+        where F: FnMut($($arg),*) -> R
+        {
+            #[inline]
+            fn call_mut(&mut self, ($($val,)*): ($($arg,)*)) -> R {
+                self($($val),*)
+            }
+        }
+
+        impl<F, $($arg,)* R> CallFn<($($arg,)*), R> for F
+        where F: Fn($($arg),*) -> R
+        {
+            #[inline]
+            fn call(&self, ($($val,)*): ($($arg,)*)) -> R {
+                self($($val),*)
+            }
+        }
+
+        impl_call!(SmallFnOnce, SmallFnMut, SmallFn; $($arg $val),*);
+        impl_call!(SendSmallFnOnce, SendSmallFnMut, SendSmallFn; $($arg $val),*);
+    };
+}
+
+impl_small_fn!();
+impl_small_fn!(A1 a1);
+impl_small_fn!(A1 a1, A2 a2);
+impl_small_fn!(A1 a1, A2 a2, A3 a3);
+impl_small_fn!(A1 a1, A2 a2, A3 a3, A4 a4);
+impl_small_fn!(A1 a1, A2 a2, A3 a3, A4 a4, A5 a5);
+impl_small_fn!(A1 a1, A2 a2, A3 a3, A4 a4, A5 a5, A6 a6);
+impl_small_fn!(A1 a1, A2 a2, A3 a3, A4 a4, A5 a5, A6 a6, A7 a7);
+impl_small_fn!(A1 a1, A2 a2, A3 a3, A4 a4, A5 a5, A6 a6, A7 a7, A8 a8);
+
+#[cfg(test)]
+mod tests {
+    use core::cell::Cell;
+
+    use super::*;
+    use crate::space::*;
+
+    struct DropCounter<'a>(&'a Cell<usize>);
+
+    impl<'a> Drop for DropCounter<'a> {
+        fn drop(&mut self) {
+            self.0.set(self.0.get() + 1);
+        }
+    }
+
+    #[test]
+    fn test_fn_once() {
+        let drops = Cell::new(0);
+        let counter = DropCounter(&drops);
+        let f: SmallFnOnce<(usize, usize), usize, S2> =
+            SmallFnOnce::new(move |a: usize, b: usize| {
+                let _counter = &counter;
+                a + b
+            });
+        assert!(!f.is_heap());
+        assert_eq!(f.call(1, 2), 3);
+        assert_eq!(drops.get(), 1);
+
+        let unit: SmallFnOnce<(), (), S1> = SmallFnOnce::new(|| {});
+        unit.call();
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn test_fn_once_heap() {
+        let drops = Cell::new(0);
+        let counter = DropCounter(&drops);
+        let data = [1usize, 2, 3, 4];
+        let f: SmallFnOnce<(usize,), usize, S1> = SmallFnOnce::new(move |i: usize| {
+            let _counter = &counter;
+            data[i]
+        });
+        assert!(f.is_heap());
+        assert_eq!(f.call(3), 4);
+        assert_eq!(drops.get(), 1);
+
+        let counter = DropCounter(&drops);
+        let uncalled: SmallFnOnce<(), [usize; 4], S1> = SmallFnOnce::new(move || {
+            let _counter = &counter;
+            data
+        });
+        drop(uncalled);
+        assert_eq!(drops.get(), 2);
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_fn_once_panic() {
+        use std::panic;
+
+        let drops = Cell::new(0);
+        let counter = DropCounter(&drops);
+        let f: SmallFnOnce<(), (), S1> = SmallFnOnce::new(move || {
+            let _counter = &counter;
+            panic!("boom");
+        });
+        let result = panic::catch_unwind(panic::AssertUnwindSafe(|| f.call()));
+        assert!(result.is_err());
+        assert_eq!(drops.get(), 1);
+    }
+
+    #[test]
+    fn test_fn_mut() {
+        let mut total = 0;
+        let mut f: SmallFnMut<(usize,), usize, S1> = SmallFnMut::new(move |n: usize| {
+            total += n;
+            total
+        });
+        assert_eq!(f.call(1), 1);
+        assert_eq!(f.call(2), 3);
+    }
+
+    #[test]
+    fn test_fn() {
+        let f: SmallFn<(u8, u8, u8), u8, S1> = SmallFn::new(|a: u8, b: u8, c: u8| a * b + c);
+        assert_eq!(f.call(2, 3, 4), 10);
+        assert_eq!(f.call(1, 1, 1), 2);
+    }
+
+    #[test]
+    fn test_borrow() {
+        let mut log = [0u8; 4];
+        let mut len = 0;
+        {
+            let mut push: SmallFnMut<(u8,), (), S2> = SmallFnMut::new(|byte: u8| {
+                log[len] = byte;
+                len += 1;
+            });
+            push.call(1);
+            push.call(2);
+        }
+        assert_eq!(log[..len], [1, 2]);
+
+        let prefix = [1u8, 2];
+        let starts_with: SmallFn<(&[u8],), bool, S1> =
+            SmallFn::new(|bytes: &[u8]| bytes.starts_with(&prefix));
+        assert!(starts_with.call(&log));
+    }
+
+    #[test]
+    fn test_send() {
+        fn assert_send<T: Send>(_: &T) {}
+
+        let f: SendSmallFnOnce<(), usize, S1> = SendSmallFnOnce::new(|| 1);
+        assert_send(&f);
+        assert_eq!(f.call(), 1);
+
+        let mut total = 0;
+        let mut f: SendSmallFnMut<(usize,), usize, S1> = SendSmallFnMut::new(|n: usize| {
+            total += n;
+            total
+        });
+        assert_send(&f);
+        assert_eq!(f.call(2), 2);
+
+        let f: SendSmallFn<(u8,), u8, S1> = SendSmallFn::new(|n: u8| n + 1);
+        assert_send(&f);
+        assert_eq!(f.call(1), 2);
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_send_thread() {
+        use std::thread;
+        use std::vec::Vec;
+
+        let tasks: Vec<SendSmallFnOnce<'static, (), usize, S2>> = (0..4usize)
+            .map(|i| SendSmallFnOnce::new(move || i * 2))
+            .collect();
+        let sum = thread::spawn(move || tasks.into_iter().map(|task| task.call()).sum::<usize>())
+            .join()
+            .unwrap();
+        assert_eq!(sum, 12);
+    }
+}
@@ -34,3 +34,177 @@ pub struct S32 {
 pub struct S64 {
     _inner: [usize; 64],
 }
+
+/// A space with the size of `Space` and at least the alignment of `A`
+///
+/// A value is only stored inline if its alignment doesn't exceed the alignment of the
+/// space, so over-aligned values, such as SIMD vectors, need a more aligned space.
+/// `A` only contributes its alignment, and can be one of the markers `A1` to `A64`,
+/// or the over-aligned type itself. The size of `Space` is rounded up to a multiple
+/// of the alignment.
+///
+/// # Example
+///
+/// ```
+/// use smallbox::space::Aligned;
+/// use smallbox::space::A16;
+/// use smallbox::space::S2;
+/// use smallbox::SmallBox;
+///
+/// #[repr(align(16))]
+/// struct Vector([f32; 4]);
+///
+/// let stacked: SmallBox<Vector, Aligned<A16, S2>> = SmallBox::new(Vector([1.0; 4]));
+/// assert!(!stacked.is_heap());
+/// ```
+#[repr(C)]
+pub struct Aligned<A, Space> {
+    _align: [A; 0],
+    _inner: Space,
+}
+
+/// Represent `N` bytes of space, aligned to a single byte
+///
+/// Only values aligned to a single byte, such as byte arrays, are stored inline.
+/// Use [`AlignedBytes`] or [`Words`] for other values.
+pub struct Bytes<const N: usize> {
+    _inner: [u8; N],
+}
+
+/// Represent `N * usize` space
+///
+/// # Example
+///
+/// ```
+/// use smallbox::space::Words;
+/// use smallbox::SmallBox;
+///
+/// let stacked: SmallBox<_, Words<3>> = SmallBox::new([1usize, 2, 3]);
+/// assert!(!stacked.is_heap());
+/// ```
+pub struct Words<const N: usize> {
+    _inner: [usize; N],
+}
+
+/// Represent `N` bytes of space, aligned to `ALIGN` bytes
+///
+/// `ALIGN` is a power of two from 1 to 64. `N` is rounded up to a multiple of `ALIGN`.
+///
+/// # Example
+///
+/// ```
+/// use smallbox::space::AlignedBytes;
+/// use smallbox::SmallBox;
+///
+/// #[repr(align(16))]
+/// struct Vector([f32; 4]);
+///
+/// let stacked: SmallBox<_, AlignedBytes<24, 8>> = SmallBox::new((1u64, 2u64, 3u64));
+/// let vector: SmallBox<_, AlignedBytes<16, 16>> = SmallBox::new(Vector([0.0; 4]));
+///
+/// assert!(!stacked.is_heap());
+/// assert!(!vector.is_heap());
+/// ```
+#[repr(C)]
+pub struct AlignedBytes<const N: usize, const ALIGN: usize>
+where Align<ALIGN>: SupportedAlign
+{
+    _align: [<Align<ALIGN> as SupportedAlign>::Marker; 0],
+    _inner: [u8; N],
+}
+
+/// An alignment of `ALIGN` bytes, mapped to its marker type by [`SupportedAlign`]
+pub struct Align<const ALIGN: usize>;
+
+/// Implemented for the alignments supported by [`AlignedBytes`]
+pub trait SupportedAlign {
+    /// The marker type with the alignment, one of `A1` to `A64`.
+    type Marker;
+}
+
+macro_rules! alignments {
+    ($($name:ident = $align:literal),*) => {
+        $(
+            #[doc = concat!("Alignment of ", stringify!($align), " bytes, to be used with `Aligned`")]
+            #[repr(align($align))]
+            pub struct $name;
+
+            impl SupportedAlign for Align<$align> {
+                type Marker = $name;
+            }
+        )*
+    };
+}
+
+alignments!(A1 = 1, A2 = 2, A4 = 4, A8 = 8, A16 = 16, A32 = 32, A64 = 64);
+
+#[cfg(test)]
+mod tests {
+    use core::mem;
+
+    use super::*;
+    use crate::SmallBox;
+
+    #[test]
+    fn test_aligned() {
+        assert_eq!(mem::align_of::<Aligned<A16, S1>>(), 16);
+        assert_eq!(mem::size_of::<Aligned<A16, S1>>(), 16);
+        assert_eq!(mem::align_of::<Aligned<A64, S8>>(), 64);
+        assert_eq!(mem::size_of::<Aligned<A64, S8>>(), 64);
+        assert_eq!(mem::align_of::<Aligned<A1, S2>>(), mem::align_of::<S2>());
+        assert_eq!(mem::size_of::<Aligned<A1, S2>>(), mem::size_of::<S2>());
+        assert_eq!(
+            mem::align_of::<Aligned<u128, [u8; 3]>>(),
+            mem::align_of::<u128>()
+        );
+    }
+
+    #[test]
+    fn test_const_generic() {
+        assert_eq!(mem::size_of::<Bytes<13>>(), 13);
+        assert_eq!(mem::align_of::<Bytes<13>>(), 1);
+        assert_eq!(mem::size_of::<Words<3>>(), 3 * mem::size_of::<usize>());
+        assert_eq!(mem::align_of::<Words<3>>(), mem::align_of::<usize>());
+        assert_eq!(mem::size_of::<AlignedBytes<24, 8>>(), 24);
+        assert_eq!(mem::align_of::<AlignedBytes<24, 8>>(), 8);
+        assert_eq!(mem::size_of::<AlignedBytes<20, 16>>(), 32);
+        assert_eq!(mem::align_of::<AlignedBytes<20, 16>>(), 16);
+
+        let bytes: SmallBox<_, Bytes<3>> = SmallBox::new([1u8, 2, 3]);
+        assert!(!bytes.is_heap());
+
+        let words: SmallBox<_, Words<3>> = SmallBox::new((1usize, 2usize, 3usize));
+        assert!(!words.is_heap());
+        assert_eq!(*words, (1, 2, 3));
+
+        let exact: SmallBox<_, AlignedBytes<24, 8>> = SmallBox::new([1u64; 3]);
+        assert!(!exact.is_heap());
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn test_const_generic_heap() {
+        let misaligned: SmallBox<_, Bytes<64>> = SmallBox::new(1u64);
+        assert!(misaligned.is_heap());
+
+        let words: SmallBox<_, Words<2>> = SmallBox::new([1usize; 3]);
+        assert!(words.is_heap());
+    }
+
+    #[test]
+    fn test_over_aligned_inline() {
+        #[repr(align(32))]
+        #[derive(Clone, Copy)]
+        struct Vector([u8; 32]);
+
+        let stacked: SmallBox<Vector, Aligned<A32, S4>> = SmallBox::new(Vector([7; 32]));
+        assert!(!stacked.is_heap());
+        assert_eq!(stacked.0, [7; 32]);
+        assert_eq!(
+            crate::sptr::from_ref(&*stacked)
+                .cast::<u8>()
+                .align_offset(32),
+            0
+        );
+    }
+}
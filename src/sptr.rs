@@ -39,4 +39,9 @@ pub fn from_ref<T: ?Sized>(val: &T) -> *const T {
     val
 }
 
+/// Returns a dangling pointer to the return type of `f`, only to carry its metadata.
+pub fn dangling_of<U, F: FnOnce() -> U>(_: &F) -> *const U {
+    core::ptr::NonNull::dangling().as_ptr()
+}
+
 pub use implementation::*;
@@ -0,0 +1,478 @@
+use core::any::Any;
+use core::cmp::Ordering;
+use core::fmt;
+use core::hash::Hash;
+use core::hash::{self};
+use core::marker::PhantomData;
+#[cfg(feature = "coerce")]
+use core::marker::Unsize;
+use core::mem::ManuallyDrop;
+use core::mem::MaybeUninit;
+use core::mem::{self};
+use core::ops;
+#[cfg(feature = "coerce")]
+use core::ops::CoerceUnsized;
+use core::ptr;
+
+use crate::space::Words;
+use crate::sptr;
+use crate::thinsmallbox;
+use crate::Global;
+use crate::SmallBox;
+
+#[cfg(feature = "coerce")]
+impl<T: ?Sized + Unsize<U>, U: ?Sized, Space> CoerceUnsized<StackBox<U, Space>>
+    for StackBox<T, Space>
+{
+}
+
+/// Box value on stack, failing to compile if it doesn't fit in the space
+///
+/// This macro is similar to `StackBox::new`, but relaxing the constraint `T: Sized`,
+/// in the same way as `smallbox!()` does for `SmallBox`.
+///
+/// You can think that it has the signature of `stackbox!<U: Sized, T: ?Sized>(val: U) -> StackBox<T, Space>`
+///
+/// # Example
+///
+/// ```
+/// #[macro_use]
+/// extern crate smallbox;
+///
+/// # fn main() {
+/// use smallbox::space::*;
+/// use smallbox::StackBox;
+///
+/// let array: StackBox<[usize], S4> = stackbox!([0usize; 2]);
+/// let is_even: StackBox<dyn Fn(u8) -> bool, S1> = stackbox!(|num: u8| num % 2 == 0);
+///
+/// assert_eq!(array.len(), 2);
+/// assert!(is_even(6));
+/// # }
+/// ```
+#[macro_export]
+macro_rules! stackbox {
+    ( $e: expr ) => {{
+        let val = $e;
+        let ptr = ::core::ptr::addr_of!(val);
+        #[allow(unsafe_code)]
+        unsafe {
+            $crate::StackBox::new_unchecked(val, ptr)
+        }
+    }};
+}
+
+/// Evaluates to a compile-time error if `U` can't be stored in `Space`.
+///
+/// `ThinSmallBox` passes its `T` to also make room for the metadata of `T` in front of
+/// the value. There is no metadata for the sized default.
+pub(crate) struct AssertFits<U, Space, T: ?Sized = U>(PhantomData<(U, Space, *const T)>);
+
+impl<U, Space, T: ?Sized> AssertFits<U, Space, T> {
+    pub(crate) const ASSERT: () = assert!(
+        fits_after::<U, Space>(thinsmallbox::inline_offset::<T, Space>()),
+        "the value does not fit in the size or alignment of the space"
+    );
+}
+
+pub(crate) const fn fits<U, Space>() -> bool {
+    fits_after::<U, Space>(0)
+}
+
+/// Returns whether `U` can be stored in `Space` at `offset`, which is aligned to `Space`.
+pub(crate) const fn fits_after<U, Space>(offset: usize) -> bool {
+    offset + mem::size_of::<U>() <= mem::size_of::<Space>()
+        && mem::align_of::<U>() <= mem::align_of::<Space>()
+}
+
+/// A box that always stores its value inline in the space and never allocates
+///
+/// Unlike `SmallBox`, there is no heap fallback: a value that doesn't fit in the size
+/// or the alignment of `Space` is rejected, at compile time by `StackBox::new` and
+/// `stackbox!()`, or at runtime by `StackBox::try_new`.
+pub struct StackBox<T: ?Sized, Space> {
+    space: MaybeUninit<Space>,
+    // only holds the metadata, the address is always null
+    ptr: *const T,
+    _phantom: PhantomData<T>,
+}
+
+/// A `StackBox` with a space of `N * usize`
+pub type StackBoxN<T, const N: usize> = StackBox<T, Words<N>>;
+
+impl<T: ?Sized, Space> StackBox<T, Space> {
+    /// Box value on stack.
+    ///
+    /// It fails to compile if the value doesn't fit in the size or the alignment of `Space`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use smallbox::space::*;
+    /// use smallbox::StackBox;
+    ///
+    /// let stacked: StackBox<_, S4> = StackBox::new([0usize; 2]);
+    ///
+    /// assert_eq!(stacked.len(), 2);
+    /// ```
+    ///
+    /// ```compile_fail
+    /// use smallbox::space::*;
+    /// use smallbox::StackBox;
+    ///
+    /// let oversize: StackBox<_, S4> = StackBox::new([0usize; 8]);
+    /// ```
+    #[inline(always)]
+    pub fn new(val: T) -> StackBox<T, Space>
+    where T: Sized {
+        stackbox!(val)
+    }
+
+    #[doc(hidden)]
+    #[inline]
+    pub unsafe fn new_unchecked<U>(val: U, ptr: *const T) -> StackBox<T, Space>
+    where U: Sized {
+        #[allow(clippy::let_unit_value)]
+        let () = AssertFits::<U, Space>::ASSERT;
+        Self::new_copy(val, ptr)
+    }
+
+    /// Box value on stack, or return it back if it doesn't fit in the size
+    /// or the alignment of `Space`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use smallbox::space::*;
+    /// use smallbox::StackBox;
+    ///
+    /// let stacked: Result<StackBox<_, S4>, _> = StackBox::try_new([0usize; 2]);
+    /// assert!(stacked.is_ok());
+    ///
+    /// let oversize: Result<StackBox<_, S4>, _> = StackBox::try_new([0usize; 8]);
+    /// assert_eq!(oversize.unwrap_err(), [0usize; 8]);
+    /// ```
+    #[inline]
+    pub fn try_new(val: T) -> Result<StackBox<T, Space>, T>
+    where T: Sized {
+        if fits::<T, Space>() {
+            let ptr = ptr::addr_of!(val);
+            Ok(unsafe { Self::new_copy(val, ptr) })
+        } else {
+            Err(val)
+        }
+    }
+
+    unsafe fn new_copy<U>(val: U, metadata_ptr: *const T) -> StackBox<T, Space>
+    where U: Sized {
+        let mut space = MaybeUninit::<Space>::uninit();
+        space.as_mut_ptr().cast::<U>().write(val);
+
+        StackBox {
+            space,
+            ptr: sptr::with_metadata_of(ptr::null::<u8>(), metadata_ptr),
+            _phantom: PhantomData,
+        }
+    }
+
+    /// Consumes the `StackBox` and returns ownership of the boxed value
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use smallbox::space::S1;
+    /// use smallbox::StackBox;
+    ///
+    /// let stacked: StackBox<_, S1> = StackBox::new([21usize]);
+    /// let val = stacked.into_inner();
+    /// assert_eq!(val[0], 21);
+    /// ```
+    #[inline]
+    pub fn into_inner(self) -> T
+    where T: Sized {
+        let this = ManuallyDrop::new(self);
+        unsafe { this.as_ptr().read() }
+    }
+
+    /// Convert the `StackBox` into a `SmallBox` with the same space.
+    ///
+    /// The value always fits in the inline space of the `SmallBox`,
+    /// so this never allocates.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// #[macro_use]
+    /// extern crate smallbox;
+    ///
+    /// # fn main() {
+    /// use smallbox::space::S1;
+    /// use smallbox::SmallBox;
+    /// use smallbox::StackBox;
+    ///
+    /// let stacked: StackBox<dyn Fn() -> usize, S1> = stackbox!(|| 42);
+    /// let small: SmallBox<dyn Fn() -> usize, S1> = stacked.into_smallbox();
+    /// assert!(!small.is_heap());
+    /// assert_eq!(small(), 42);
+    /// # }
+    /// ```
+    #[inline]
+    pub fn into_smallbox(self) -> SmallBox<T, Space> {
+        let this = ManuallyDrop::new(self);
+        let val: &T = &this;
+        unsafe { SmallBox::new_copy(val, sptr::from_ref(val), Global) }
+    }
+
+    #[inline]
+    unsafe fn as_ptr(&self) -> *const T {
+        sptr::with_metadata_of(self.space.as_ptr(), self.ptr)
+    }
+
+    #[inline]
+    unsafe fn as_mut_ptr(&mut self) -> *mut T {
+        sptr::with_metadata_of_mut(self.space.as_mut_ptr(), self.ptr)
+    }
+}
+
+macro_rules! impl_any {
+    ($($bound:ident),*) => {
+        impl<Space> StackBox<dyn Any $(+ $bound)*, Space> {
+            /// Attempt to downcast the box to a concrete type.
+            ///
+            /// # Examples
+            ///
+            /// ```
+            /// #[macro_use]
+            /// extern crate smallbox;
+            ///
+            /// # fn main() {
+            /// use core::any::Any;
+            ///
+            /// use smallbox::space::*;
+            /// use smallbox::StackBox;
+            ///
+            /// let num: StackBox<dyn Any, S1> = stackbox!(1234u32);
+            /// assert_eq!(num.downcast::<u32>().unwrap().into_inner(), 1234);
+            /// # }
+            /// ```
+            #[inline]
+            pub fn downcast<T: Any>(self) -> Result<StackBox<T, Space>, Self> {
+                if self.is::<T>() {
+                    unsafe { Ok(self.downcast_unchecked()) }
+                } else {
+                    Err(self)
+                }
+            }
+
+            /// Downcast the box to a concrete type without checking the type.
+            ///
+            /// # Safety
+            ///
+            /// The boxed value must be a `T`, calling this method with the incorrect
+            /// type is undefined behavior.
+            #[inline]
+            pub unsafe fn downcast_unchecked<T: Any>(self) -> StackBox<T, Space> {
+                debug_assert!(self.is::<T>());
+                let this = ManuallyDrop::new(self);
+
+                StackBox {
+                    space: ptr::read(&this.space),
+                    ptr: this.ptr.cast(),
+                    _phantom: PhantomData,
+                }
+            }
+        }
+    };
+}
+
+impl_any!();
+impl_any!(Send);
+impl_any!(Send, Sync);
+
+impl<T: ?Sized, Space> From<StackBox<T, Space>> for SmallBox<T, Space> {
+    fn from(stacked: StackBox<T, Space>) -> Self {
+        stacked.into_smallbox()
+    }
+}
+
+impl<T: ?Sized, Space> ops::Deref for StackBox<T, Space> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.as_ptr() }
+    }
+}
+
+impl<T: ?Sized, Space> ops::DerefMut for StackBox<T, Space> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.as_mut_ptr() }
+    }
+}
+
+impl<T: ?Sized, Space> ops::Drop for StackBox<T, Space> {
+    fn drop(&mut self) {
+        unsafe { ptr::drop_in_place::<T>(&mut **self) }
+    }
+}
+
+impl<T: Clone, Space> Clone for StackBox<T, Space>
+where T: Sized
+{
+    fn clone(&self) -> Self {
+        let val: &T = self;
+        // `self` already proved that `T` fits in `Space`
+        unsafe { Self::new_copy(val.clone(), sptr::from_ref(val)) }
+    }
+}
+
+impl<T: ?Sized + fmt::Display, Space> fmt::Display for StackBox<T, Space> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(&**self, f)
+    }
+}
+
+impl<T: ?Sized + fmt::Debug, Space> fmt::Debug for StackBox<T, Space> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+impl<T: ?Sized + PartialEq, Space> PartialEq for StackBox<T, Space> {
+    fn eq(&self, other: &StackBox<T, Space>) -> bool {
+        PartialEq::eq(&**self, &**other)
+    }
+}
+
+impl<T: ?Sized + PartialOrd, Space> PartialOrd for StackBox<T, Space> {
+    fn partial_cmp(&self, other: &StackBox<T, Space>) -> Option<Ordering> {
+        PartialOrd::partial_cmp(&**self, &**other)
+    }
+}
+
+impl<T: ?Sized + Ord, Space> Ord for StackBox<T, Space> {
+    fn cmp(&self, other: &StackBox<T, Space>) -> Ordering {
+        Ord::cmp(&**self, &**other)
+    }
+}
+
+impl<T: ?Sized + Eq, Space> Eq for StackBox<T, Space> {}
+
+impl<T: ?Sized + Hash, Space> Hash for StackBox<T, Space> {
+    fn hash<H: hash::Hasher>(&self, state: &mut H) {
+        (**self).hash(state);
+    }
+}
+
+unsafe impl<T: ?Sized + Send, Space> Send for StackBox<T, Space> {}
+unsafe impl<T: ?Sized + Sync, Space> Sync for StackBox<T, Space> {}
+
+#[cfg(test)]
+mod tests {
+    use core::any::Any;
+    use core::cell::Cell;
+
+    use super::StackBox;
+    use crate::space::*;
+    use crate::SmallBox;
+
+    #[test]
+    fn test_basic() {
+        let stacked: StackBox<usize, S1> = StackBox::new(1234usize);
+        assert_eq!(*stacked, 1234);
+
+        let stacked: StackBox<(usize, usize), S2> = StackBox::new((0, 1));
+        assert_eq!(*stacked, (0, 1));
+    }
+
+    #[test]
+    fn test_try_new() {
+        let fit = StackBox::<_, S1>::try_new([1usize]);
+        assert_eq!(*fit.unwrap(), [1]);
+
+        let oversize = StackBox::<_, S1>::try_new([1usize, 2]);
+        assert_eq!(oversize.unwrap_err(), [1, 2]);
+
+        #[repr(align(64))]
+        #[derive(Debug, PartialEq)]
+        struct OverAligned(u8);
+        let misaligned = StackBox::<_, S1>::try_new(OverAligned(0));
+        assert_eq!(misaligned.unwrap_err(), OverAligned(0));
+    }
+
+    #[test]
+    #[deny(unsafe_code)]
+    fn test_macro() {
+        let stacked: StackBox<dyn Any, S1> = stackbox!(1234usize);
+        assert_eq!(stacked.downcast_ref::<usize>(), Some(&1234));
+
+        let array: StackBox<[usize], S2> = stackbox!([0usize, 1]);
+        assert_eq!(*array, [0, 1]);
+
+        let is_even: StackBox<dyn Fn(u8) -> bool, S1> = stackbox!(|num: u8| num % 2 == 0);
+        assert!(!is_even(5));
+        assert!(is_even(6));
+    }
+
+    #[test]
+    fn test_drop() {
+        #[allow(dead_code)]
+        struct Struct<'a>(&'a Cell<bool>, u8);
+        impl<'a> Drop for Struct<'a> {
+            fn drop(&mut self) {
+                self.0.set(true);
+            }
+        }
+
+        let flag = Cell::new(false);
+        let stacked: StackBox<_, S2> = StackBox::new(Struct(&flag, 0));
+        assert!(!flag.get());
+        drop(stacked);
+        assert!(flag.get());
+
+        let flag = Cell::new(false);
+        let stacked: StackBox<_, S2> = StackBox::new(Struct(&flag, 0));
+        let val = stacked.into_inner();
+        assert!(!flag.get());
+        drop(val);
+        assert!(flag.get());
+    }
+
+    #[test]
+    fn test_zst() {
+        struct ZSpace([usize; 0]);
+
+        let zst: StackBox<[usize], ZSpace> = stackbox!([1usize; 0]);
+        assert_eq!(*zst, [1usize; 0]);
+        assert!(StackBox::<_, ZSpace>::try_new([1usize; 1]).is_err());
+    }
+
+    #[test]
+    fn test_downcast() {
+        let stacked: StackBox<dyn Any, S1> = stackbox!(0x01u32);
+        assert_eq!(*stacked.downcast::<u32>().unwrap(), 0x01);
+
+        let stacked_send: StackBox<dyn Any + Send, S1> = stackbox!(0x01u32);
+        assert_eq!(*stacked_send.downcast::<u32>().unwrap(), 0x01);
+
+        let stacked_sync: StackBox<dyn Any + Send + Sync, S1> = stackbox!(0x01u32);
+        assert_eq!(*stacked_sync.downcast::<u32>().unwrap(), 0x01);
+
+        let stacked_sync: StackBox<dyn Any + Send + Sync, S1> = stackbox!(0x01u32);
+        assert_eq!(*unsafe { stacked_sync.downcast_unchecked::<u32>() }, 0x01);
+
+        let mismatched: StackBox<dyn Any, S1> = stackbox!(0x01u32);
+        assert!(mismatched.downcast::<u8>().is_err());
+    }
+
+    #[test]
+    fn test_into_smallbox() {
+        let stacked: StackBox<[usize], S2> = stackbox!([0usize, 1]);
+        let small: SmallBox<[usize], S2> = stacked.into();
+        assert!(!small.is_heap());
+        assert_eq!(*small, [0, 1]);
+
+        let stacked: StackBox<_, S2> = StackBox::new([2usize, 3]);
+        assert_eq!(stacked.clone(), stacked);
+        assert_eq!(stacked.into_smallbox().into_inner(), [2, 3]);
+    }
+}
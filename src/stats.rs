@@ -0,0 +1,409 @@
+//! Statistics about where `SmallBox` values are stored
+//!
+//! This module is only available with the `stats` feature. Every `SmallBox` placement is
+//! counted as either inline or on the heap, and every heap deallocation is counted as well.
+//! The totals can be read with [`snapshot`] and cleared with [`reset`].
+//!
+//! Each event carries the type and space the value was placed with. The heap memory is
+//! freed under the same key, even if the box has since been coerced or downcast, so every
+//! `SmallBox` keeps one more word to remember it.
+//!
+//! To break the numbers down, for example by type name and space, install a
+//! [`Recorder`] with [`set_recorder`] and forward each [`Event`] to a metrics system.
+//!
+//! # Example
+//!
+//! ```
+//! use smallbox::space::S1;
+//! use smallbox::stats;
+//! use smallbox::SmallBox;
+//!
+//! let before = stats::snapshot();
+//! let stacked: SmallBox<_, S1> = SmallBox::new(0usize);
+//! let after = stats::snapshot();
+//!
+//! assert!(after.inline > before.inline);
+//! ```
+
+use core::alloc::Layout;
+use core::mem;
+use core::sync::atomic::AtomicUsize;
+use core::sync::atomic::Ordering;
+
+static INLINE: AtomicUsize = AtomicUsize::new(0);
+static HEAP: AtomicUsize = AtomicUsize::new(0);
+static HEAP_BYTES: AtomicUsize = AtomicUsize::new(0);
+static FREED: AtomicUsize = AtomicUsize::new(0);
+static FREED_BYTES: AtomicUsize = AtomicUsize::new(0);
+
+const UNINITIALIZED: usize = 0;
+const INITIALIZING: usize = 1;
+const INITIALIZED: usize = 2;
+
+static STATE: AtomicUsize = AtomicUsize::new(UNINITIALIZED);
+static mut RECORDER: &dyn Recorder = &NopRecorder;
+
+/// The totals counted since the start of the program or the last [`reset`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct Snapshot {
+    /// Number of values stored in the inline space.
+    pub inline: usize,
+    /// Number of values stored on the heap.
+    pub heap: usize,
+    /// Number of bytes allocated on the heap.
+    pub heap_bytes: usize,
+    /// Number of heap allocations freed.
+    pub freed: usize,
+    /// Number of heap bytes freed.
+    pub freed_bytes: usize,
+}
+
+/// Where a value was placed, or that its heap memory was freed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EventKind {
+    /// The value was stored in the inline space.
+    Inline,
+    /// The value was stored on the heap.
+    Heap,
+    /// The heap memory of the value was freed.
+    Free,
+}
+
+/// A single placement or deallocation of a `SmallBox` value.
+#[derive(Clone, Copy, Debug)]
+pub struct Event {
+    kind: EventKind,
+    type_name: &'static str,
+    size: usize,
+    align: usize,
+    space_size: usize,
+    space_align: usize,
+}
+
+impl Event {
+    /// Returns what happened to the value.
+    #[inline]
+    pub fn kind(&self) -> EventKind {
+        self.kind
+    }
+
+    /// Returns the name of the type of the value.
+    #[inline]
+    pub fn type_name(&self) -> &'static str {
+        self.type_name
+    }
+
+    /// Returns the size of the value in bytes.
+    #[inline]
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Returns the alignment of the value in bytes.
+    #[inline]
+    pub fn align(&self) -> usize {
+        self.align
+    }
+
+    /// Returns the size of the inline space in bytes.
+    #[inline]
+    pub fn space_size(&self) -> usize {
+        self.space_size
+    }
+
+    /// Returns the alignment of the inline space in bytes.
+    #[inline]
+    pub fn space_align(&self) -> usize {
+        self.space_align
+    }
+}
+
+/// A sink for `SmallBox` events, installed with [`set_recorder`].
+pub trait Recorder: Sync {
+    /// Called for every placement and heap deallocation of a `SmallBox` value.
+    fn record(&self, event: &Event);
+}
+
+struct NopRecorder;
+
+impl Recorder for NopRecorder {
+    fn record(&self, _: &Event) {}
+}
+
+/// The error returned by [`set_recorder`] if a recorder has already been installed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SetRecorderError(());
+
+/// Install the global recorder.
+///
+/// The recorder can only be installed once, further calls return an error.
+pub fn set_recorder(recorder: &'static dyn Recorder) -> Result<(), SetRecorderError> {
+    match STATE.compare_exchange(
+        UNINITIALIZED,
+        INITIALIZING,
+        Ordering::Acquire,
+        Ordering::Relaxed,
+    ) {
+        Ok(_) => {
+            unsafe { RECORDER = recorder };
+            STATE.store(INITIALIZED, Ordering::Release);
+            Ok(())
+        }
+        Err(_) => Err(SetRecorderError(())),
+    }
+}
+
+/// Returns the totals counted since the start of the program or the last [`reset`].
+pub fn snapshot() -> Snapshot {
+    Snapshot {
+        inline: INLINE.load(Ordering::Relaxed),
+        heap: HEAP.load(Ordering::Relaxed),
+        heap_bytes: HEAP_BYTES.load(Ordering::Relaxed),
+        freed: FREED.load(Ordering::Relaxed),
+        freed_bytes: FREED_BYTES.load(Ordering::Relaxed),
+    }
+}
+
+/// Reset all totals to zero.
+pub fn reset() {
+    INLINE.store(0, Ordering::Relaxed);
+    HEAP.store(0, Ordering::Relaxed);
+    HEAP_BYTES.store(0, Ordering::Relaxed);
+    FREED.store(0, Ordering::Relaxed);
+    FREED_BYTES.store(0, Ordering::Relaxed);
+}
+
+#[inline]
+pub(crate) fn record<U: ?Sized, Space>(kind: EventKind, layout: Layout) {
+    let size = layout.size();
+    match kind {
+        EventKind::Inline => {
+            INLINE.fetch_add(1, Ordering::Relaxed);
+        }
+        EventKind::Heap => {
+            HEAP.fetch_add(1, Ordering::Relaxed);
+            HEAP_BYTES.fetch_add(size, Ordering::Relaxed);
+        }
+        EventKind::Free => {
+            FREED.fetch_add(1, Ordering::Relaxed);
+            FREED_BYTES.fetch_add(size, Ordering::Relaxed);
+        }
+    }
+
+    if STATE.load(Ordering::Acquire) == INITIALIZED {
+        let recorder = unsafe { RECORDER };
+        recorder.record(&Event {
+            kind,
+            type_name: core::any::type_name::<U>(),
+            size,
+            align: layout.align(),
+            space_size: mem::size_of::<Space>(),
+            space_align: mem::align_of::<Space>(),
+        });
+    }
+}
+
+#[cfg(all(test, feature = "alloc"))]
+mod tests {
+    use core::mem::MaybeUninit;
+    use core::sync::atomic::AtomicUsize;
+    use core::sync::atomic::Ordering;
+
+    use super::*;
+    use crate::space::*;
+    use crate::CompactBox;
+    use crate::SmallBox;
+
+    #[derive(Clone)]
+    struct Tracked(#[allow(dead_code)] [usize; 2]);
+
+    #[cfg(feature = "std")]
+    struct Unwound(#[allow(dead_code)] [usize; 2]);
+
+    struct Compacted(#[allow(dead_code)] usize);
+
+    struct Keyed(#[allow(dead_code)] [usize; 2]);
+
+    trait KeyedDyn: core::any::Any {}
+
+    impl KeyedDyn for Keyed {}
+
+    struct TestRecorder {
+        inline: AtomicUsize,
+        heap: AtomicUsize,
+        free: AtomicUsize,
+        unwound_heap: AtomicUsize,
+        unwound_free: AtomicUsize,
+        compacted_inline: AtomicUsize,
+        keyed: [AtomicUsize; 2],
+        keyed_dyn: [AtomicUsize; 2],
+    }
+
+    // the counters are global, so only count the types used by these tests
+    impl Recorder for TestRecorder {
+        fn record(&self, event: &Event) {
+            if event.type_name().ends_with("Unwound") {
+                let counter = match event.kind() {
+                    EventKind::Inline => panic!("unexpected inline event"),
+                    EventKind::Heap => &self.unwound_heap,
+                    EventKind::Free => &self.unwound_free,
+                };
+                counter.fetch_add(1, Ordering::SeqCst);
+                return;
+            }
+            let keyed = if event.type_name().ends_with("KeyedDyn") {
+                Some(&self.keyed_dyn)
+            } else if event.type_name().ends_with("Keyed") {
+                Some(&self.keyed)
+            } else {
+                None
+            };
+            if let Some(keyed) = keyed {
+                let counter = match event.kind() {
+                    EventKind::Inline => panic!("unexpected inline event"),
+                    EventKind::Heap => &keyed[0],
+                    EventKind::Free => &keyed[1],
+                };
+                counter.fetch_add(1, Ordering::SeqCst);
+                return;
+            }
+            if event.type_name().ends_with("Compacted") {
+                assert_eq!(event.kind(), EventKind::Inline);
+                self.compacted_inline.fetch_add(1, Ordering::SeqCst);
+                return;
+            }
+            if !event.type_name().ends_with("Tracked") {
+                return;
+            }
+            assert_eq!(event.size(), mem::size_of::<Tracked>());
+            let counter = match event.kind() {
+                EventKind::Inline => {
+                    assert_eq!(event.space_size(), mem::size_of::<S2>());
+                    &self.inline
+                }
+                EventKind::Heap => {
+                    assert_eq!(event.space_size(), mem::size_of::<S1>());
+                    &self.heap
+                }
+                EventKind::Free => &self.free,
+            };
+            counter.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    static RECORDER: TestRecorder = TestRecorder {
+        inline: AtomicUsize::new(0),
+        heap: AtomicUsize::new(0),
+        free: AtomicUsize::new(0),
+        unwound_heap: AtomicUsize::new(0),
+        unwound_free: AtomicUsize::new(0),
+        compacted_inline: AtomicUsize::new(0),
+        keyed: [AtomicUsize::new(0), AtomicUsize::new(0)],
+        keyed_dyn: [AtomicUsize::new(0), AtomicUsize::new(0)],
+    };
+
+    fn keyed_counts(counters: &[AtomicUsize; 2]) -> (usize, usize) {
+        (
+            counters[0].load(Ordering::SeqCst),
+            counters[1].load(Ordering::SeqCst),
+        )
+    }
+
+    #[test]
+    fn test_recorder() {
+        // the recorder is shared with the other tests, whichever installs it first
+        let _ = set_recorder(&RECORDER);
+        assert!(set_recorder(&RECORDER).is_err());
+
+        let stacked: SmallBox<_, S2> = SmallBox::new(Tracked([0, 1]));
+        let cloned = stacked.clone();
+        assert_eq!(RECORDER.inline.load(Ordering::SeqCst), 2);
+
+        let heaped = stacked.resize::<S1>();
+        assert_eq!(RECORDER.heap.load(Ordering::SeqCst), 1);
+        assert_eq!(RECORDER.free.load(Ordering::SeqCst), 0);
+
+        drop(cloned);
+        drop(heaped);
+        assert_eq!(RECORDER.free.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_snapshot() {
+        // other tests may run concurrently, so the totals can only be bounded from below
+        let before = snapshot();
+        let stacked: SmallBox<_, S4> = SmallBox::new([0usize; 4]);
+        let heaped: SmallBox<_, S1> = SmallBox::new([0usize; 4]);
+        drop(heaped);
+        let after = snapshot();
+
+        assert!(after.inline > before.inline);
+        assert!(after.heap > before.heap);
+        assert!(after.heap_bytes >= before.heap_bytes + mem::size_of::<[usize; 4]>());
+        assert!(after.freed > before.freed);
+        drop(stacked);
+
+        // the only test that resets, as it would break the bounds above; others may still
+        // count concurrently, so check that the large heap totals from before are gone
+        let heaped: SmallBox<[MaybeUninit<u8>], S1> = SmallBox::new_zeroed_slice(1 << 16);
+        drop(heaped);
+        let before = snapshot();
+        reset();
+        let after = snapshot();
+        assert!(after.heap_bytes < before.heap_bytes);
+        assert!(after.heap_bytes < 1 << 16);
+        assert!(after.freed_bytes < 1 << 16);
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_recorder_unwind() {
+        let _ = set_recorder(&RECORDER);
+
+        let result = std::panic::catch_unwind(|| {
+            SmallBox::<_, S1>::new_with(|| -> Unwound { panic!("init failed") })
+        });
+        assert!(result.is_err());
+        assert_eq!(RECORDER.unwound_heap.load(Ordering::SeqCst), 1);
+        assert_eq!(RECORDER.unwound_free.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_recorder_compact() {
+        let _ = set_recorder(&RECORDER);
+
+        let compact: CompactBox<_, S1> = CompactBox::new(Compacted(0));
+        assert_eq!(RECORDER.compacted_inline.load(Ordering::SeqCst), 1);
+
+        // the value stays inline, so it is not counted twice
+        let small: SmallBox<_, S1> = compact.into_smallbox();
+        assert!(!small.is_heap());
+        assert_eq!(small.0, 0);
+        assert_eq!(RECORDER.compacted_inline.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_recorder_dyn() {
+        let _ = set_recorder(&RECORDER);
+
+        // placed as `Keyed` and freed as `dyn KeyedDyn`
+        let heaped: SmallBox<dyn KeyedDyn, S1> = crate::smallbox!(Keyed([0, 1]));
+        assert!(heaped.is_heap());
+        drop(heaped);
+        assert_eq!(keyed_counts(&RECORDER.keyed), (1, 1));
+
+        // placed as `dyn KeyedDyn` and freed as `Keyed`
+        let boxed: ::alloc::boxed::Box<dyn KeyedDyn> = ::alloc::boxed::Box::new(Keyed([0, 1]));
+        let heaped: SmallBox<dyn KeyedDyn, S1> = SmallBox::from_box(boxed);
+        let keyed = heaped.downcast_dyn::<Keyed>().ok().unwrap();
+        drop(keyed);
+        assert_eq!(keyed_counts(&RECORDER.keyed_dyn), (1, 1));
+
+        let thin: crate::ThinSmallBox<dyn KeyedDyn, S1> = crate::thin_smallbox!(Keyed([0, 1]));
+        assert!(thin.is_heap());
+        drop(thin);
+        assert_eq!(keyed_counts(&RECORDER.keyed_dyn), (2, 2));
+        assert_eq!(keyed_counts(&RECORDER.keyed), (1, 1));
+    }
+}
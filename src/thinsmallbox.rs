@@ -0,0 +1,517 @@
+use core::alloc::Layout;
+use core::cmp::Ordering;
+use core::fmt;
+use core::hash::Hash;
+use core::hash::{self};
+use core::marker::PhantomData;
+use core::mem::ManuallyDrop;
+use core::mem::MaybeUninit;
+use core::mem::{self};
+use core::ops;
+use core::ptr;
+use core::ptr::NonNull;
+
+use crate::error::handle_alloc_error;
+use crate::hook;
+use crate::smallbox::inline_ptr;
+use crate::sptr;
+use crate::stackbox;
+#[cfg(not(feature = "alloc"))]
+use crate::stackbox::AssertFits;
+#[cfg(feature = "stats")]
+use crate::stats::EventKind;
+#[cfg(feature = "stats")]
+use crate::stats::{self};
+use crate::Allocator;
+use crate::Global;
+
+/// Box value on stack or on heap depending on its size, in a `ThinSmallBox`
+///
+/// This macro is the `ThinSmallBox` counterpart of `smallbox!()`, relaxing the
+/// constraint `T: Sized` of `ThinSmallBox::new`.
+///
+/// You can think that it has the signature of `thin_smallbox!<U: Sized, T: ?Sized>(val: U) -> ThinSmallBox<T, Space>`
+///
+/// # Example
+///
+/// ```
+/// #[macro_use]
+/// extern crate smallbox;
+///
+/// # fn main() {
+/// use core::fmt::Debug;
+///
+/// use smallbox::space::S4;
+/// use smallbox::ThinSmallBox;
+///
+/// let debug: ThinSmallBox<dyn Debug, S4> = thin_smallbox!([0usize; 2]);
+///
+/// assert!(!debug.is_heap());
+/// assert_eq!(format!("{:?}", debug), "[0, 0]");
+/// # }
+/// ```
+#[macro_export]
+macro_rules! thin_smallbox {
+    ( $e: expr ) => {{
+        let val = $e;
+        let ptr = ::core::ptr::addr_of!(val);
+        #[allow(unsafe_code)]
+        unsafe {
+            $crate::ThinSmallBox::new_unchecked(val, ptr)
+        }
+    }};
+}
+
+/// An optimized box that stores the pointer metadata next to the value
+///
+/// `SmallBox<dyn Trait, Space>` keeps a fat pointer beside the space, which is two
+/// words larger than `Space`. `ThinSmallBox` writes the metadata as a header right
+/// before the value instead, inside the space for a value stored inline, and inside
+/// the heap block otherwise. The box itself is `Space` plus a single pointer.
+///
+/// The header only holds the metadata, one word for trait objects and slices and
+/// nothing for sized values, since the address is known from where the value is.
+/// It takes room in the space: the value starts after the header, rounded up to the
+/// alignment of `Space`, and a value that doesn't fit in what is left is stored on
+/// the heap. The heap memory comes from the global allocator.
+///
+/// # Example
+///
+/// ```
+/// #[macro_use]
+/// extern crate smallbox;
+///
+/// # fn main() {
+/// use core::any::Any;
+/// use core::mem;
+///
+/// use smallbox::space::S4;
+/// use smallbox::ThinSmallBox;
+///
+/// let thin: ThinSmallBox<dyn Any, S4> = thin_smallbox!(1234usize);
+///
+/// assert!(!thin.is_heap());
+/// assert_eq!(thin.downcast_ref::<usize>(), Some(&1234));
+/// assert_eq!(
+///     mem::size_of::<ThinSmallBox<dyn Any, S4>>(),
+///     mem::size_of::<S4>() + mem::size_of::<usize>()
+/// );
+/// # }
+/// ```
+pub struct ThinSmallBox<T: ?Sized, Space> {
+    space: MaybeUninit<Space>,
+    // the address of the value on heap, or `inline_ptr()` if it is stored inline
+    ptr: NonNull<u8>,
+    _phantom: PhantomData<T>,
+}
+
+impl<T: ?Sized, Space> ThinSmallBox<T, Space> {
+    /// Box value on stack or on heap depending on its size.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # #[cfg(feature = "alloc")]
+    /// # {
+    /// use smallbox::space::S4;
+    /// use smallbox::ThinSmallBox;
+    ///
+    /// let small: ThinSmallBox<_, S4> = ThinSmallBox::new([0usize; 2]);
+    /// let large: ThinSmallBox<_, S4> = ThinSmallBox::new([1usize; 8]);
+    ///
+    /// assert!(!small.is_heap());
+    /// assert!(large.is_heap());
+    /// assert_eq!(large[7], 1);
+    /// # }
+    /// ```
+    #[inline(always)]
+    #[track_caller]
+    pub fn new(val: T) -> ThinSmallBox<T, Space>
+    where T: Sized {
+        thin_smallbox!(val)
+    }
+
+    #[doc(hidden)]
+    #[inline]
+    #[track_caller]
+    pub unsafe fn new_unchecked<U>(val: U, metadata_ptr: *const T) -> ThinSmallBox<T, Space>
+    where U: Sized {
+        // without a global allocator there is nowhere to spill, so refuse at compile time
+        #[cfg(not(feature = "alloc"))]
+        #[allow(clippy::let_unit_value)]
+        let () = AssertFits::<U, Space, T>::ASSERT;
+
+        let layout = Layout::new::<U>();
+
+        // events are recorded as a `T`, as that is all the free knows about the value
+        let ptr = if fits::<U, T, Space>() {
+            #[cfg(feature = "stats")]
+            stats::record::<T, Space>(EventKind::Inline, layout);
+            NonNull::new_unchecked(inline_ptr())
+        } else {
+            hook::heap_fallback::<U, Space>(layout);
+            let (block_layout, offset) = heap_layout::<T>(layout);
+            let block = match Global.allocate(block_layout) {
+                Ok(block) => block,
+                Err(err) => handle_alloc_error(err.layout()),
+            };
+            #[cfg(feature = "stats")]
+            stats::record::<T, Space>(EventKind::Heap, block_layout);
+            NonNull::new_unchecked(block.as_ptr().add(offset))
+        };
+
+        // only give up `val` once its place is set up, so it is dropped if the hook panics
+        let val = ManuallyDrop::new(val);
+        let mut this: ThinSmallBox<T, Space> = ThinSmallBox {
+            space: MaybeUninit::uninit(),
+            ptr,
+            _phantom: PhantomData,
+        };
+        let dst = this.value_mut_ptr();
+        write_header(dst, metadata_ptr);
+        ptr::copy_nonoverlapping(sptr::from_ref(&*val).cast::<u8>(), dst, layout.size());
+        this
+    }
+
+    /// Returns true if data is allocated on heap.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # #[cfg(feature = "alloc")]
+    /// # {
+    /// use smallbox::space::S1;
+    /// use smallbox::ThinSmallBox;
+    ///
+    /// let heaped: ThinSmallBox<_, S1> = ThinSmallBox::new([0usize; 2]);
+    /// assert!(heaped.is_heap());
+    /// # }
+    /// ```
+    #[inline]
+    pub fn is_heap(&self) -> bool {
+        self.ptr.as_ptr() != inline_ptr()
+    }
+
+    /// Consumes the `ThinSmallBox` and returns ownership of the boxed value
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use smallbox::space::S4;
+    /// use smallbox::ThinSmallBox;
+    ///
+    /// let thin: ThinSmallBox<_, S4> = ThinSmallBox::new([21usize, 56]);
+    /// assert_eq!(thin.into_inner(), [21, 56]);
+    /// ```
+    #[inline]
+    pub fn into_inner(self) -> T
+    where T: Sized {
+        let mut this = ManuallyDrop::new(self);
+        unsafe {
+            let val = this.as_ptr().read();
+            this.dealloc(Layout::new::<T>());
+            val
+        }
+    }
+
+    /// Free the heap memory, if any, without dropping the boxed value.
+    unsafe fn dealloc(&mut self, layout: Layout) {
+        if self.is_heap() {
+            let (block_layout, offset) = heap_layout::<T>(layout);
+            #[cfg(feature = "stats")]
+            stats::record::<T, Space>(EventKind::Free, block_layout);
+            let block = NonNull::new_unchecked(self.ptr.as_ptr().sub(offset));
+            Global.deallocate(block, block_layout);
+        }
+    }
+
+    #[inline]
+    unsafe fn value_mut_ptr(&mut self) -> *mut u8 {
+        if self.is_heap() {
+            self.ptr.as_ptr()
+        } else {
+            self.space
+                .as_mut_ptr()
+                .cast::<u8>()
+                .add(inline_offset::<T, Space>())
+        }
+    }
+
+    #[inline]
+    unsafe fn as_ptr(&self) -> *const T {
+        let val: *const u8 = if self.is_heap() {
+            self.ptr.as_ptr()
+        } else {
+            self.space
+                .as_ptr()
+                .cast::<u8>()
+                .add(inline_offset::<T, Space>())
+        };
+        read_header(val)
+    }
+
+    #[inline]
+    unsafe fn as_mut_ptr(&mut self) -> *mut T {
+        let val = self.value_mut_ptr();
+        sptr::with_metadata_of_mut(val, read_header::<T>(val))
+    }
+}
+
+/// The size of the header holding the metadata of `T`, that is a pointer to `T`
+/// without its leading data pointer.
+const fn header_size<T: ?Sized>() -> usize {
+    mem::size_of::<*const T>() - mem::size_of::<*const u8>()
+}
+
+/// Write the metadata of `metadata_ptr` to the header right before `val`.
+unsafe fn write_header<T: ?Sized>(val: *mut u8, metadata_ptr: *const T) {
+    let metadata = sptr::from_ref(&metadata_ptr)
+        .cast::<u8>()
+        .add(mem::size_of::<*const u8>());
+    ptr::copy_nonoverlapping(metadata, val.sub(header_size::<T>()), header_size::<T>());
+}
+
+/// Returns a pointer to the value at `val`, with the metadata from the header before it.
+unsafe fn read_header<T: ?Sized>(val: *const u8) -> *const T {
+    let mut ptr = MaybeUninit::<*const T>::uninit();
+    let dst = ptr.as_mut_ptr().cast::<u8>();
+    dst.cast::<*const u8>().write(val);
+    ptr::copy_nonoverlapping(
+        val.sub(header_size::<T>()),
+        dst.add(mem::size_of::<*const u8>()),
+        header_size::<T>(),
+    );
+    ptr.assume_init()
+}
+
+/// The offset of a value stored inline, right after the header and aligned to `Space`.
+pub(crate) const fn inline_offset<T: ?Sized, Space>() -> usize {
+    let align = mem::align_of::<Space>();
+    (header_size::<T>() + align - 1) / align * align
+}
+
+const fn fits<U, T: ?Sized, Space>() -> bool {
+    stackbox::fits_after::<U, Space>(inline_offset::<T, Space>())
+}
+
+/// The layout of a heap block holding the header and a value with the given layout,
+/// and the offset of the value in the block.
+fn heap_layout<T: ?Sized>(layout: Layout) -> (Layout, usize) {
+    Layout::array::<u8>(header_size::<T>())
+        .and_then(|header| header.extend(layout))
+        .expect("capacity overflow")
+}
+
+impl<T: ?Sized, Space> ops::Deref for ThinSmallBox<T, Space> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.as_ptr() }
+    }
+}
+
+impl<T: ?Sized, Space> ops::DerefMut for ThinSmallBox<T, Space> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.as_mut_ptr() }
+    }
+}
+
+impl<T: ?Sized, Space> ops::Drop for ThinSmallBox<T, Space> {
+    fn drop(&mut self) {
+        unsafe {
+            let layout = Layout::for_value::<T>(&*self);
+            ptr::drop_in_place::<T>(&mut **self);
+            self.dealloc(layout);
+        }
+    }
+}
+
+impl<T: ?Sized + fmt::Display, Space> fmt::Display for ThinSmallBox<T, Space> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(&**self, f)
+    }
+}
+
+impl<T: ?Sized + fmt::Debug, Space> fmt::Debug for ThinSmallBox<T, Space> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+impl<T: ?Sized + PartialEq, Space> PartialEq for ThinSmallBox<T, Space> {
+    fn eq(&self, other: &ThinSmallBox<T, Space>) -> bool {
+        PartialEq::eq(&**self, &**other)
+    }
+}
+
+impl<T: ?Sized + PartialOrd, Space> PartialOrd for ThinSmallBox<T, Space> {
+    fn partial_cmp(&self, other: &ThinSmallBox<T, Space>) -> Option<Ordering> {
+        PartialOrd::partial_cmp(&**self, &**other)
+    }
+}
+
+impl<T: ?Sized + Ord, Space> Ord for ThinSmallBox<T, Space> {
+    fn cmp(&self, other: &ThinSmallBox<T, Space>) -> Ordering {
+        Ord::cmp(&**self, &**other)
+    }
+}
+
+impl<T: ?Sized + Eq, Space> Eq for ThinSmallBox<T, Space> {}
+
+impl<T: ?Sized + Hash, Space> Hash for ThinSmallBox<T, Space> {
+    fn hash<H: hash::Hasher>(&self, state: &mut H) {
+        (**self).hash(state);
+    }
+}
+
+unsafe impl<T: ?Sized + Send, Space> Send for ThinSmallBox<T, Space> {}
+unsafe impl<T: ?Sized + Sync, Space> Sync for ThinSmallBox<T, Space> {}
+
+#[cfg(test)]
+mod tests {
+    use core::any::Any;
+    use core::cell::Cell;
+    use core::mem;
+
+    use super::ThinSmallBox;
+    use crate::space::*;
+    use crate::SmallBox;
+
+    #[test]
+    fn test_basic() {
+        let stacked: ThinSmallBox<usize, S2> = ThinSmallBox::new(1234);
+        assert!(!stacked.is_heap());
+        assert_eq!(*stacked, 1234);
+
+        let any: ThinSmallBox<dyn Any, S4> = thin_smallbox!(1234usize);
+        assert!(!any.is_heap());
+        assert_eq!(any.downcast_ref::<usize>(), Some(&1234));
+
+        let mut slice: ThinSmallBox<[u8], S4> = thin_smallbox!([1u8, 2, 3]);
+        slice[0] = 4;
+        assert!(!slice.is_heap());
+        assert_eq!(*slice, [4, 2, 3]);
+
+        let zst: ThinSmallBox<dyn Any, S2> = thin_smallbox!(());
+        assert!(!zst.is_heap());
+        assert!(zst.is::<()>());
+    }
+
+    #[test]
+    fn test_size() {
+        let word = mem::size_of::<usize>();
+        assert_eq!(
+            mem::size_of::<ThinSmallBox<dyn Any, S4>>(),
+            mem::size_of::<S4>() + word
+        );
+        assert_eq!(
+            mem::size_of::<ThinSmallBox<[u8], S4>>(),
+            mem::size_of::<S4>() + word
+        );
+        assert_eq!(
+            mem::size_of::<Option<ThinSmallBox<dyn Any, S4>>>(),
+            mem::size_of::<ThinSmallBox<dyn Any, S4>>()
+        );
+    }
+
+    #[test]
+    fn test_size_at_equal_capacity() {
+        // both store two words inline, on top of two words of pointer and metadata, and
+        // with the `stats` feature, `SmallBox` also keeps how to record its events
+        let stats = if cfg!(feature = "stats") {
+            mem::size_of::<fn()>()
+        } else {
+            0
+        };
+        let thin: ThinSmallBox<dyn Any, Words<3>> = thin_smallbox!([1usize, 2]);
+        let small: SmallBox<dyn Any, S2> = crate::smallbox!([1usize, 2]);
+        assert!(!thin.is_heap());
+        assert!(!small.is_heap());
+        assert_eq!(mem::size_of_val(&thin) + stats, mem::size_of_val(&small));
+
+        let thin: ThinSmallBox<[u8], Words<3>> = thin_smallbox!([1u8; 16]);
+        let small: SmallBox<[u8], S2> = crate::smallbox!([1u8; 16]);
+        assert!(!thin.is_heap());
+        assert!(!small.is_heap());
+        assert_eq!(mem::size_of_val(&thin) + stats, mem::size_of_val(&small));
+
+        let thin: ThinSmallBox<u64, S1> = ThinSmallBox::new(1);
+        let small: SmallBox<u64, S1> = SmallBox::new(1);
+        assert!(!thin.is_heap());
+        assert!(!small.is_heap());
+        assert_eq!(mem::size_of_val(&thin) + stats, mem::size_of_val(&small));
+    }
+
+    #[test]
+    fn test_unaligned_space() {
+        let stacked: ThinSmallBox<[u8], [u8; 24]> = thin_smallbox!([1u8, 2, 3, 4]);
+        assert!(!stacked.is_heap());
+        assert_eq!(*stacked, [1, 2, 3, 4]);
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn test_heap() {
+        let heaped: ThinSmallBox<dyn Any, S2> = thin_smallbox!([1usize, 2]);
+        assert!(heaped.is_heap());
+        assert_eq!(heaped.downcast_ref::<[usize; 2]>(), Some(&[1, 2]));
+
+        // the header alone doesn't fit in the space
+        let heaped: ThinSmallBox<dyn Any, [u8; 4]> = thin_smallbox!(());
+        assert!(heaped.is_heap());
+        assert!(heaped.is::<()>());
+
+        #[repr(align(64))]
+        struct OverAligned(u8);
+        let heaped: ThinSmallBox<OverAligned, S64> = ThinSmallBox::new(OverAligned(7));
+        assert!(heaped.is_heap());
+        assert_eq!(heaped.0, 7);
+        assert_eq!(heaped.into_inner().0, 7);
+    }
+
+    #[test]
+    fn test_drop() {
+        #[allow(dead_code)]
+        struct Struct<'a>(&'a Cell<bool>, u8);
+        impl<'a> Drop for Struct<'a> {
+            fn drop(&mut self) {
+                self.0.set(true);
+            }
+        }
+        trait Flagged {}
+        impl<'a> Flagged for Struct<'a> {}
+
+        let flag = Cell::new(false);
+        let stacked: ThinSmallBox<dyn Flagged, S4> = thin_smallbox!(Struct(&flag, 0));
+        assert!(!flag.get());
+        drop(stacked);
+        assert!(flag.get());
+
+        let flag = Cell::new(false);
+        let stacked: ThinSmallBox<_, S4> = ThinSmallBox::new(Struct(&flag, 0));
+        let val = stacked.into_inner();
+        assert!(!flag.get());
+        drop(val);
+        assert!(flag.get());
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn test_drop_heap() {
+        #[allow(dead_code)]
+        struct Struct<'a>(&'a Cell<bool>, [usize; 4]);
+        impl<'a> Drop for Struct<'a> {
+            fn drop(&mut self) {
+                self.0.set(true);
+            }
+        }
+        trait Flagged {}
+        impl<'a> Flagged for Struct<'a> {}
+
+        let flag = Cell::new(false);
+        let heaped: ThinSmallBox<dyn Flagged, S1> = thin_smallbox!(Struct(&flag, [0; 4]));
+        assert!(heaped.is_heap());
+        drop(heaped);
+        assert!(flag.get());
+    }
+}